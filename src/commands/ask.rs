@@ -0,0 +1,167 @@
+use anyhow::{bail, Result};
+use async_openai::{
+    types::responses::{CreateResponseArgs, Input, InputContent, InputItem, InputMessageArgs, InputMessageType, Role}
+};
+use clap::Args;
+use std::fs;
+
+use crate::{commands::{extract_output_text, output_text, prompt, rerank::{self, RerankOpts}, search, thread}, db::open_db, fs as ufs, llm::{self, CallOpts, GenOpts}, state::ProjectState};
+
+#[derive(Args, Debug, Default)]
+pub struct AskArgs {
+    /// Вопрос о коде проекта (можно опустить при --prompt с --file)
+    #[arg(required_unless_present = "prompt")] pub question: Option<String>,
+    /// Шаблон из библиотеки (prompt save) вместо голого вопроса
+    #[arg(long)] pub prompt: Option<String>,
+    /// Файл для {code} шаблона; без {code} в шаблоне — целиком в [CONTEXT]
+    #[arg(long)] pub file: Option<String>,
+    /// Продолжить текущий диалог (thread new / thread switch): прошлые реплики идут в запрос,
+    /// вопрос и ответ дописываются в историю
+    #[arg(long)] pub thread: bool,
+    /// Сколько чанков из поиска положить в контекст
+    #[arg(long, default_value_t = 8)] pub k: usize,
+    /// Брать в контекст только чанки этих видов (function,class,...); по умолчанию — все
+    #[arg(long, value_delimiter = ',')] pub kinds: Vec<String>,
+    /// В контекст — только комментарий и объявление каждого чанка, без тела (вопросы об API)
+    #[arg(long)] pub signatures_only: bool,
+    /// Вес совпадения вопроса с именем символа чанка: bm25 × (1 + W × доля слов в имени); 0 — чистый bm25
+    #[arg(long, default_value_t = 1.0)] pub name_boost: f64,
+    #[command(flatten)] pub rerank: RerankOpts,
+    #[arg(long)] pub model: Option<String>,
+    #[arg(long, default_value_t = 900)] pub max_output: u32,
+    /// Для скриптов: код выхода ≠ 0, если ответ пустой, отказ модели или в индексе ничего не нашлось
+    #[arg(long)] pub strict: bool,
+    #[command(flatten)] pub gen_opts: GenOpts,
+    #[command(flatten)] pub call: CallOpts,
+}
+
+const SYSTEM: &str = "Ты отвечаешь на вопросы о кодовой базе. Опирайся только на фрагменты из [CONTEXT], \
+ничего не выдумывай. К каждому утверждению ставь ссылку на фрагмент в виде path:line. \
+Если ответа во фрагментах нет — прямо скажи, что в контексте его нет.";
+
+/// RAG: FTS-поиск по вопросу → top-k чанков с заголовками path:line → ответ модели со ссылками
+pub async fn run(a: AskArgs) -> Result<()> {
+    let AskArgs { question, prompt, file, thread: use_thread, k, kinds, signatures_only, name_boost, rerank: rr, model, max_output, strict, gen_opts, call } = a;
+    let root = ufs::detect_project_root()?;
+    let st = ProjectState::load(&root)?;
+    let ns = st.active_namespace();
+    let conn = open_db(&root)?;
+    let thread_id = if use_thread { Some(thread::current(&conn, &st)?) } else { None };
+    let code = file.as_deref()
+        .map(|f| ufs::read_text_sanitized(&root.join(f)))
+        .transpose()?;
+
+    // 1) retrieval — по вопросу; без вопроса (шаблон над файлом) контекст только из файла
+    let mut hits = match &question {
+        Some(q) => search::fts_search(&conn, Some(&ns), q, rr.pool(k), &kinds, name_boost)?,
+        None => Vec::new(),
+    };
+    if rr.rerank && let Some(q) = &question && !hits.is_empty() {
+        hits = rerank::rerank(&root, &conn, q, hits, k, &rr, &call).await?;
+    }
+    if signatures_only { search::to_signatures(&root, &mut hits); }
+    let task = match &prompt {
+        Some(name) => prompt::render(&root, name, question.as_deref(), code.as_deref())?,
+        None => question.clone().unwrap_or_default(),
+    };
+    if hits.is_empty() && code.is_none() {
+        let kinds = if kinds.is_empty() { String::new() } else { format!(", kinds {}", kinds.join(",")) };
+        let why = format!("по вопросу ничего не найдено в индексе (namespace {ns}{kinds}); проверьте scan/index");
+        if strict { bail!("strict: insufficient data: {why}"); }
+        bail!("{why}");
+    }
+
+    // 2) контекст: заголовок фрагмента = то, чем модель будет ссылаться
+    let mut context = String::new();
+    if let (Some(f), Some(code)) = (&file, &code) && !task.contains(code.as_str()) {
+        context.push_str(&format!("### {f}:1-{} file\n{}\n\n", code.lines().count(), code.trim_end()));
+    }
+    for h in &hits {
+        let sym = h.symbol_label().map(|s| format!(" {} {s}", h.kind)).unwrap_or_default();
+        context.push_str(&format!("### {}:{}-{}{sym}\n{}\n\n", h.path, h.begin_line, h.end_line, h.text.trim_end()));
+    }
+    let user = format!("[CONTEXT]\n{context}[QUESTION]\n{task}");
+
+    // 3) запрос (Responses API)
+    let model = llm::resolve_model(model.as_deref().unwrap_or("gpt-4.1-mini"));
+    let mut input = vec![
+        InputItem::Message(InputMessageArgs::default()
+            .kind(InputMessageType::Message)
+            .role(Role::System)
+            .content(InputContent::TextInput(gen_opts.system_prompt(SYSTEM)))
+            .build()?),
+    ];
+    // --thread: прошлые реплики — без их [CONTEXT], иначе запрос растёт с каждым ходом
+    let history = match &thread_id {
+        Some(id) => thread::history(&conn, id)?,
+        None => Vec::new(),
+    };
+    for (role, content) in &history {
+        let role = if role == "assistant" { Role::Assistant } else { Role::User };
+        input.push(InputItem::Message(InputMessageArgs::default()
+            .role(role)
+            .content(InputContent::TextInput(content.clone()))
+            .build()?));
+    }
+    input.push(InputItem::Message(InputMessageArgs::default()
+        .role(Role::User)
+        .content(InputContent::TextInput(user.clone()))
+        .build()?));
+    let mut args = CreateResponseArgs::default();
+    args.model(model.clone())
+        .max_output_tokens(llm::clamp_max_output(&model, max_output))
+        .input(Input::Items(input));
+    gen_opts.apply(&mut args);
+    let args = args.build()?;
+
+    let (req_path, resp_path) = llm::request_log_paths(&root, "ask", &model)?;
+    fs::write(&req_path, serde_json::to_vec_pretty(&args)?)?;
+    if call.dry_run {
+        llm::print_dry_run(&args, &req_path, &call);
+        return Ok(());
+    }
+    llm::check_budget(&root, &args, &call)?;
+    let (resp, cached) = match llm::create_response(&root, args, &call).await {
+        Ok(r) => r,
+        Err(e) => {
+            // история диалога — отдельной секцией, чтобы был виден её вклад в размер запроса
+            let sent = if history.is_empty() { user.clone() } else {
+                let turns = history.iter().map(|(role, content)| format!("{role}: {content}")).collect::<Vec<_>>().join("\n");
+                format!("[HISTORY]\n{turns}\n{user}")
+            };
+            llm::report_failed_request(&req_path, &gen_opts.system_prompt(SYSTEM), &sent);
+            return Err(e.into());
+        }
+    };
+    fs::write(&resp_path, serde_json::to_vec_pretty(&resp)?)?;
+    llm::warn_if_incomplete(&resp);
+
+    // 4) ответ + источники
+    let answer = extract_output_text(&resp);
+    println!("{answer}\n");
+    if let Some(id) = &thread_id {
+        thread::append(&conn, id, "user", &task, None)?;
+        thread::append(&conn, id, "assistant", &answer, resp.usage.as_ref().map(|u| u.total_tokens))?;
+    }
+    println!("Sources:");
+    if let Some(f) = &file {
+        println!("  {f}");
+    }
+    for h in &hits {
+        println!("  {}:{}", h.path, h.begin_line);
+    }
+    llm::report_usage(&root, "ask", &model, &resp, cached);
+    eprintln!("— raw request:  {}", req_path.display());
+    eprintln!("— raw response: {}", resp_path.display());
+
+    if strict {
+        // вывод уже напечатан — скрипт видит и ответ, и причину отказа
+        if let Some(r) = llm::refusal(&resp) {
+            bail!("strict: model refused: {r}");
+        }
+        if output_text(&resp).is_none() {
+            bail!("strict: empty answer");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use rusqlite::params;
+use serde_json::json;
+use std::{path::Path, time::Instant};
+
+use crate::{commands::{ask, index, scan}, db::{self, open_db}, fs as ufs, llm::CallOpts, state::ProjectState};
+
+/// Замер scan → index (полный) → FTS-запросы → ask --dry-run; отчёт в JSON.
+/// Всё идёт на копии БД (VACUUM INTO): индекс пользователя и его стратегия чанкинга не меняются
+pub async fn run(queries: Vec<String>, repeat: usize, out: Option<String>) -> Result<()> {
+    let root = ufs::detect_project_root()?;
+    let ns = ProjectState::load(&root)?.active_namespace();
+
+    let copy = root.join(".gptcli/bench.sqlite");
+    {
+        let conn = open_db(&root)?;
+        let _ = std::fs::remove_file(&copy);
+        conn.execute("VACUUM INTO ?1", [copy.to_string_lossy()])?;
+    }
+    db::redirect_db(copy.clone());
+    let report = phases(&root, &ns, queries, repeat).await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", copy.display()));
+    }
+
+    let text = serde_json::to_string_pretty(&report?)?;
+    match out {
+        Some(p) => { std::fs::write(&p, text)?; eprintln!("— bench report: {p}"); }
+        None => println!("{text}"),
+    }
+    Ok(())
+}
+
+async fn phases(root: &Path, ns: &str, queries: Vec<String>, repeat: usize) -> Result<serde_json::Value> {
+    // --- scan
+    let t = Instant::now();
+    // --full: замеряем чтение и хэширование, а не сверку mtime
+    scan::run(scan::ScanArgs { full: true, ..Default::default() })?;
+    let scan_secs = t.elapsed().as_secs_f64();
+    let conn = open_db(root)?;
+    let (files, bytes): (i64, i64) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(size),0) FROM files WHERE namespace=?1",
+        params![ns], |r| Ok((r.get(0)?, r.get(1)?)))?;
+
+    // --- index: принудительно весь namespace, иначе замеряем пустой проход
+    conn.execute("UPDATE files SET indexed_sha=NULL WHERE namespace=?1", params![ns])?;
+    let t = Instant::now();
+    index::run(index::IndexArgs { quiet: true, ..Default::default() })?;
+    let index_secs = t.elapsed().as_secs_f64();
+    let chunks: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM chunks c JOIN files f ON f.id=c.file_id WHERE f.namespace=?1",
+        params![ns], |r| r.get(0))?;
+
+    // --- FTS: заданные запросы или самые частые имена символов
+    let queries = if queries.is_empty() {
+        let mut q = conn.prepare(
+            "SELECT t.name FROM tags t JOIN files f ON f.id=t.file_id
+              WHERE f.namespace=?1 GROUP BY t.name ORDER BY COUNT(*) DESC, t.name LIMIT 5")?;
+        q.query_map(params![ns], |r| r.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()?
+    } else { queries };
+    let mut q = conn.prepare(
+        "SELECT COUNT(*) FROM fts_chunks JOIN chunks c ON c.id=fts_chunks.rowid
+           JOIN files f ON f.id=c.file_id
+          WHERE fts_chunks MATCH ?1 AND f.namespace=?2")?;
+    let mut hits = 0i64;
+    let t = Instant::now();
+    for _ in 0..repeat {
+        for s in &queries {
+            // фраза в кавычках: имена с :: и прочей пунктуацией не ломают синтаксис FTS5
+            let phrase = format!("\"{}\"", s.replace('"', "\"\""));
+            hits += q.query_row(params![phrase, ns], |r| r.get::<_, i64>(0))?;
+        }
+    }
+    let query_secs = t.elapsed().as_secs_f64();
+    let n_queries = queries.len() * repeat;
+
+    // --- ask: retrieval и сборка запроса, без вызова API
+    let question = queries.join(" ");
+    let t = Instant::now();
+    let asked = ask::run(ask::AskArgs {
+        question: Some(question.clone()), k: 8, max_output: 900, name_boost: 1.0,
+        call: CallOpts { dry_run: true, quiet: true, ..Default::default() },
+        ..Default::default()
+    }).await;
+    let ask_secs = t.elapsed().as_secs_f64();
+
+    let per_sec = |n: f64, secs: f64| if secs > 0.0 { n / secs } else { 0.0 };
+    let report = json!({
+        "namespace": ns,
+        "phases": [
+            { "name": "scan", "secs": scan_secs, "files": files, "bytes": bytes,
+              "files_per_sec": per_sec(files as f64, scan_secs) },
+            { "name": "index", "secs": index_secs, "files": files, "chunks": chunks,
+              "files_per_sec": per_sec(files as f64, index_secs), "chunks_per_sec": per_sec(chunks as f64, index_secs) },
+            { "name": "query", "secs": query_secs, "queries": n_queries, "hits": hits,
+              "queries_per_sec": per_sec(n_queries as f64, query_secs), "terms": queries },
+            { "name": "ask", "secs": ask_secs, "dry_run": true, "question": question,
+              "error": asked.err().map(|e| format!("{e:#}")) },
+        ],
+    });
+    Ok(report)
+}
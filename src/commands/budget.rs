@@ -1,9 +1,33 @@
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::Args;
 use reqwest::header::{AUTHORIZATION, HeaderMap};
+use rusqlite::{params, Connection};
 use std::env;
 
-pub async fn run() -> Result<()> {
+use crate::{db::open_db, fs as ufs, pricing};
+
+#[derive(Args, Debug)]
+pub struct BudgetArgs {
+    /// Вместо лимитов API — расход из локального usage_log по периодам и моделям
+    #[arg(long)]
+    pub local: bool,
+    /// С --local: группировка day|week|month
+    #[arg(long, default_value = "day", requires = "local")]
+    pub by: String,
+    /// С --local: с этого дня включительно (YYYY-MM-DD, UTC)
+    #[arg(long, requires = "local")]
+    pub from: Option<String>,
+    /// С --local: по этот день включительно (YYYY-MM-DD, UTC)
+    #[arg(long, requires = "local")]
+    pub to: Option<String>,
+    /// С --local: CSV вместо таблицы (без итоговой строки; cost пустой, если цена модели неизвестна)
+    #[arg(long, requires = "local")]
+    pub csv: bool,
+}
+
+pub async fn run(a: BudgetArgs) -> Result<()> {
+    if a.local { return run_local(&a); }
     let key = env::var("OPENAI_API_KEY")?;
     let url = "https://api.openai.com/v1/models"; // лёгкий эндпоинт
     let client = reqwest::Client::builder().build()?;
@@ -28,10 +52,140 @@ pub async fn run() -> Result<()> {
 
     // на некоторых тарифах ещё бывают minute/day/burst-линейки; можно вывести все x-ratelimit-* для наглядности:
     for (k,v) in hs.iter() {
-        if k.as_str().starts_with("x-ratelimit-") {
-            if let Ok(s) = v.to_str() { println!("{:>28}: {}", k.as_str(), s); }
-        }
+        if k.as_str().starts_with("x-ratelimit-")
+            && let Ok(s) = v.to_str() { println!("{:>28}: {}", k.as_str(), s); }
     }
 
     Ok(())
 }
+
+/// Что уже потрачено по данным usage_log (пишут вызовы LLM и эмбеддингов), без обращения к API
+fn run_local(a: &BudgetArgs) -> Result<()> {
+    let period = period_expr(&a.by)?;
+    let root = ufs::detect_project_root()?;
+    let conn = open_db(&root)?;
+    let from = a.from.as_deref().map(|d| check_day(&conn, "--from", d)).transpose()?;
+    let to = a.to.as_deref().map(|d| check_day(&conn, "--to", d)).transpose()?;
+    let rows = usage_rows(&conn, period, from.as_deref(), to.as_deref())?;
+    if rows.is_empty() {
+        match (&from, &to) {
+            (None, None) => println!("usage_log is empty (no LLM calls recorded yet)"),
+            (f, t) => println!("no usage in range {}..{}", f.as_deref().unwrap_or(""), t.as_deref().unwrap_or("")),
+        }
+        return Ok(());
+    }
+    if a.csv {
+        println!("{},model,calls,input,output,total,cost", a.by);
+        for (p, model, calls, input, output, total) in rows {
+            // неизвестная цена — пустое поле, чтобы колонка оставалась числовой
+            let cost = pricing::cost_for(&model, input, output).map(|c| format!("{c:.6}")).unwrap_or_default();
+            println!("{},{},{calls},{input},{output},{total},{cost}", csv_field(&p), csv_field(&model));
+        }
+        return Ok(());
+    }
+    println!("{:<10}  {:<24} {:>6} {:>10} {:>10} {:>10} {:>10}", a.by, "model", "calls", "input", "output", "total", "cost");
+    let mut sum = (0i64, 0i64, 0i64, 0i64);
+    // итоговая стоимость известна, только если известна каждая строка
+    let mut cost_sum = Some(0.0);
+    for (p, model, calls, input, output, total) in rows {
+        let cost = pricing::cost_for(&model, input, output);
+        cost_sum = cost_sum.zip(cost).map(|(a, b)| a + b);
+        println!("{p:<10}  {model:<24} {calls:>6} {input:>10} {output:>10} {total:>10} {:>10}", pricing::fmt_cost(cost));
+        sum = (sum.0 + calls, sum.1 + input, sum.2 + output, sum.3 + total);
+    }
+    println!("{:<10}  {:<24} {:>6} {:>10} {:>10} {:>10} {:>10}", "total", "", sum.0, sum.1, sum.2, sum.3, pricing::fmt_cost(cost_sum));
+    Ok(())
+}
+
+type UsageRow = (String, String, i64, i64, i64, i64);
+
+/// Группировка --by: день, неделя (ISO-подобная метка 2024-W05, неделя с понедельника) или месяц
+fn period_expr(by: &str) -> Result<&'static str> {
+    Ok(match by {
+        "day" => "date(ts,'unixepoch')",
+        "week" => "strftime('%Y-W%W',ts,'unixepoch')",
+        "month" => "strftime('%Y-%m',ts,'unixepoch')",
+        other => bail!("unknown --by '{other}': day, week or month"),
+    })
+}
+
+/// YYYY-MM-DD; проверяет сама SQLite, чтобы фильтр сравнивал с тем же форматом, что date(ts,'unixepoch')
+fn check_day(conn: &Connection, flag: &str, day: &str) -> Result<String> {
+    let norm: Option<String> = conn.query_row("SELECT date(?1)", params![day], |r| r.get(0))?;
+    match norm {
+        Some(d) if d == day => Ok(d),
+        _ => bail!("{flag} '{day}': expected a date as YYYY-MM-DD"),
+    }
+}
+
+/// Периоды и модели с суммами; --from/--to включительно, по дню в UTC
+fn usage_rows(conn: &Connection, period: &str, from: Option<&str>, to: Option<&str>) -> Result<Vec<UsageRow>> {
+    let mut q = conn.prepare(&format!(
+        "SELECT {period}, model, COUNT(*), SUM(input_tokens), SUM(output_tokens), SUM(total_tokens)
+           FROM usage_log
+          WHERE (?1 IS NULL OR date(ts,'unixepoch') >= ?1) AND (?2 IS NULL OR date(ts,'unixepoch') <= ?2)
+          GROUP BY 1, 2 ORDER BY 1, 2"))?;
+    let rows = q.query_map(params![from, to], |r| Ok((
+        r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, i64>(2)?,
+        r.get::<_, i64>(3)?, r.get::<_, i64>(4)?, r.get::<_, i64>(5)?,
+    )))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE usage_log(ts INTEGER, command TEXT, model TEXT, input_tokens INTEGER,
+                                    output_tokens INTEGER, total_tokens INTEGER, namespace TEXT);
+             -- 2024-01-01 (пн), 2024-01-07 (вс), 2024-01-08 (пн), 2024-02-01
+             INSERT INTO usage_log VALUES
+               (1704110400,'ask','m',10,1,11,'p'), (1704628800,'ask','m',20,2,22,'p'),
+               (1704715200,'ask','m',30,3,33,'p'), (1706788800,'explain','m',40,4,44,'p');").unwrap();
+        conn
+    }
+
+    fn totals(rows: &[UsageRow]) -> Vec<(&str, i64)> {
+        rows.iter().map(|r| (r.0.as_str(), r.5)).collect()
+    }
+
+    #[test]
+    fn usage_rows_group_by_week_and_month() {
+        let conn = log();
+        let weeks = usage_rows(&conn, period_expr("week").unwrap(), None, None).unwrap();
+        assert_eq!(totals(&weeks), vec![("2024-W01", 33), ("2024-W02", 33), ("2024-W05", 44)]);
+        let months = usage_rows(&conn, period_expr("month").unwrap(), None, None).unwrap();
+        assert_eq!(totals(&months), vec![("2024-01", 66), ("2024-02", 44)]);
+        assert!(period_expr("year").is_err());
+    }
+
+    #[test]
+    fn usage_rows_filter_by_inclusive_range() {
+        let conn = log();
+        let days = usage_rows(&conn, period_expr("day").unwrap(), Some("2024-01-07"), Some("2024-01-08")).unwrap();
+        assert_eq!(totals(&days), vec![("2024-01-07", 22), ("2024-01-08", 33)]);
+        assert!(usage_rows(&conn, period_expr("day").unwrap(), Some("2025-01-01"), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn check_day_rejects_non_dates() {
+        let conn = log();
+        assert_eq!(check_day(&conn, "--from", "2024-02-29").unwrap(), "2024-02-29");
+        for bad in ["2024-13-01", "2024-2-1", "yesterday"] {
+            assert!(check_day(&conn, "--from", bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("gpt-4.1"), "gpt-4.1");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+}
@@ -1,6 +1,738 @@
+use anyhow::{bail, Result};
+use clap::Args;
+use regex::Regex;
+use rusqlite::{params, Transaction};
+use std::collections::HashMap;
 
-use anyhow::Result;
-pub fn run() -> Result<()> {
-    println!("chunk: TODO — разрезать файлы на логические чанки и подготовить к индексации");
+use crate::{db::open_db, fs as ufs, plan::Plan, state::ProjectState};
+
+/// Стратегия чанкинга: базовое «тег → чанк» плюс необязательные доработки поверх
+#[derive(Args, Debug, Clone, Default)]
+pub struct Strategy {
+    /// Дробить тела символов на части примерно по N токенов (вместо «тег → чанк»)
+    #[arg(long = "chunk-by-tokens", value_name = "N")]
+    pub by_tokens: Option<usize>,
+    /// Отдельный чанк kind='file_header' из начального комментария/докстринга файла
+    #[arg(long)]
+    pub file_headers: bool,
+    /// Многострочные комментарии (от N строк, по умолчанию 3) — отдельными чанками kind='comment'
+    /// с привязкой к ближайшему символу
+    #[arg(long, value_name = "MIN_LINES", num_args = 0..=1, default_missing_value = "3")]
+    pub comment_chunks: Option<usize>,
+    /// Лямбды C++ (от N строк, по умолчанию 3) внутри функций — отдельными чанками kind='lambda'
+    /// с символом объемлющей функции; сама функция остаётся целым чанком
+    #[arg(long, value_name = "MIN_LINES", num_args = 0..=1, default_missing_value = "3")]
+    pub lambda_chunks: Option<usize>,
+    /// Чанки длиннее N строк резать на окна по N строк (часть помечается как `foo [2/3]`)
+    #[arg(long, value_name = "N")]
+    pub max_chunk_lines: Option<usize>,
+    /// Сколько строк соседние окна --max-chunk-lines делят между собой
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "max_chunk_lines")]
+    pub overlap_lines: usize,
+}
+
+impl Strategy {
+    /// Несовместимые значения флагов — до того, как index/chunk что-то прочтёт
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let Some(max) = self.max_chunk_lines {
+            if max == 0 { bail!("--max-chunk-lines must be at least 1"); }
+            // шаг окна max - overlap: иначе окно на каждую строку и FTS из почти одинаковых чанков
+            if self.overlap_lines >= max {
+                bail!("--overlap-lines ({}) must be less than --max-chunk-lines ({max})", self.overlap_lines);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn specs(&self, tags: &[TagSpan], text: &str, lang: &str) -> Vec<ChunkSpec> {
+        let total_lines = (text.lines().count() as i64).max(1);
+        let indent_src = uses_indent_blocks(lang).then_some(text);
+        let mut specs = build_chunks_v1(tags, total_lines, indent_src);
+        // привязка к символам — по целым чанкам, до дробления по токенам
+        let comments = self.comment_chunks
+            .map(|n| comment_chunks(&specs, text, lang, n.max(2), self.file_headers))
+            .unwrap_or_default();
+        let lambdas = self.lambda_chunks
+            .map(|n| lambda_chunks(&specs, text, lang, n.max(2)))
+            .unwrap_or_default();
+        if let Some(n) = self.max_chunk_lines {
+            specs = split_by_lines(specs, n, self.overlap_lines);
+        }
+        if let Some(n) = self.by_tokens {
+            specs = split_by_tokens(specs, text, n);
+        }
+        if self.file_headers && let Some((begin, end)) = leading_comment(text, lang) {
+            specs.insert(0, ChunkSpec { kind: "file_header".into(), symbol: None, begin_line: begin, end_line: end, part: None });
+        }
+        specs.extend(comments);
+        specs.extend(lambdas);
+        specs
+    }
+}
+
+#[derive(Args, Debug, Default)]
+pub struct ChunkArgs {
+    #[command(flatten)] pub strategy: Strategy,
+    /// Показать, сколько чанков изменится, ничего не записывая
+    #[arg(long)] pub dry_run: bool,
+}
+
+/// Тег в объёме, нужном чанкингу: из ctags (index) или из таблицы tags (chunk)
+#[derive(Debug, Clone)]
+pub(crate) struct TagSpan {
+    pub(crate) name: String,
+    pub(crate) kind: String,
+    pub(crate) line: i64,
+    pub(crate) end_line: Option<i64>,
+    pub(crate) scope: Option<String>,
+    pub(crate) language: String,    // язык по ctags ("C++", "Rust", ...); "" — теги, сохранённые без языка
+}
+
+/// Пересобрать чанки по уже сохранённым тегам, без ctags: для экспериментов с гранулярностью.
+/// Берём только файлы с актуальными тегами — изменённые на диске ждут index.
+pub fn run(args: ChunkArgs) -> Result<()> {
+    args.strategy.validate()?;
+    let root = ufs::detect_project_root()?;
+    let st = ProjectState::load(&root)?;
+    let ns = st.active_namespace();
+    let mut conn = open_db(&root)?;
+
+    let mut q = conn.prepare(
+        "SELECT id, path, COALESCE(mtime,0), COALESCE(lang_guess,'') FROM files
+          WHERE namespace=?1 AND indexed_sha IS NOT NULL AND indexed_sha=sha AND index_error IS NULL
+          ORDER BY path")?;
+    let files = q.query_map(params![ns], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?, r.get::<_, i64>(2)?, r.get::<_, String>(3)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    drop(q);
+
+    let tx = conn.transaction()?;
+    let (mut written, mut removed, mut kept, mut unreadable) = (0usize, 0usize, 0usize, 0usize);
+    {
+        let mut qt = tx.prepare(
+            "SELECT name, kind, line, end_line, scope, COALESCE(lang,'') FROM tags WHERE file_id=?1 AND line IS NOT NULL ORDER BY line")?;
+        for (id, path, mtime, lang) in &files {
+            let Ok(text) = ufs::read_text_sanitized(&root.join(path)) else {
+                eprintln!("warn: не удалось прочитать {path}");
+                unreadable += 1;
+                continue;
+            };
+            let tags = qt.query_map(params![id], |r| Ok(TagSpan {
+                name: r.get(0)?, kind: r.get(1)?, line: r.get(2)?, end_line: r.get(3)?, scope: r.get(4)?, language: r.get(5)?,
+            }))?.collect::<rusqlite::Result<Vec<_>>>()?;
+            let (w, r, k) = rechunk_file(&tx, *id, *mtime, &tags, &text, lang, &args.strategy)?;
+            written += w; removed += r; kept += k;
+        }
+    }
+    if args.dry_run {
+        tx.rollback()?;
+        let mut plan = Plan::new("chunk");
+        plan.add("files rechunked", files.len() - unreadable, &[]);
+        plan.add("unreadable files", unreadable, &[]);
+        plan.add("chunks written", written, &[]);
+        plan.add("chunks removed", removed, &[]);
+        plan.add("chunks unchanged", kept, &[]);
+        plan.print();
+        return Ok(());
+    }
+    tx.commit()?;
+    println!("chunk: {} files, {written} written, {removed} removed, {kept} unchanged", files.len() - unreadable);
     Ok(())
 }
+
+/// Чанки одного файла по его тегам: стратегия → сопоставление со старыми чанками (sync_chunks).
+/// Возвращает (записано, удалено, без изменений).
+pub(crate) fn rechunk_file(
+    tx: &Transaction, file_id: i64, mtime: i64, tags: &[TagSpan], text: &str, lang: &str, strategy: &Strategy,
+) -> Result<(usize, usize, usize)> {
+    sync_chunks(tx, file_id, mtime, strategy.specs(tags, text, lang), text)
+}
+
+/// Инкрементальное обновление чанков файла: чанк с тем же (kind, symbol, sha) сохраняет
+/// свой id (и будущие эмбеддинги), у него только сдвигаются строки и номер части. Остальные — delete/insert.
+/// Возвращает (записано, удалено, без изменений).
+pub(crate) fn sync_chunks(tx: &Transaction, file_id: i64, mtime: i64, specs: Vec<ChunkSpec>, file_text: &str) -> Result<(usize, usize, usize)> {
+    let mut q = tx.prepare_cached(
+        "SELECT id, kind, COALESCE(symbol,''), COALESCE(sha,'') FROM chunks WHERE file_id=?1 ORDER BY begin_line")?;
+    let mut old: HashMap<(String, String, String), Vec<i64>> = HashMap::new();
+    for row in q.query_map(params![file_id], |r| Ok((r.get::<_, i64>(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))? {
+        let (id, kind, symbol, sha) = row?;
+        old.entry((kind, symbol, sha)).or_default().push(id);
+    }
+
+    let mut upd = tx.prepare_cached("UPDATE chunks SET begin_line=?1, end_line=?2, part=?3, parts=?4 WHERE id=?5")?;
+    let mut ins = tx.prepare_cached(
+        "INSERT INTO chunks(file_id,kind,symbol,begin_line,end_line,sha,mtime,text,tokens,part,parts)
+         VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11)")?;
+    let mut del = tx.prepare_cached("DELETE FROM chunks WHERE id=?1")?;
+
+    let (mut written, mut removed, mut kept) = (0usize, 0usize, 0usize);
+    for c in specs {
+        let text = ufs::slice_lines(file_text, c.begin_line, c.end_line);
+        let sha = sha256_str(&text);
+        let key = (c.kind.clone(), c.symbol.clone().unwrap_or_default(), sha.clone());
+        let (part, parts) = c.part.unzip();
+        match old.get_mut(&key).and_then(|ids| ids.pop()) {
+            Some(id) => {
+                upd.execute(params![c.begin_line, c.end_line, part, parts, id])?;
+                kept += 1;
+            }
+            None => {
+                let tokens = estimate_tokens(&text);
+                ins.execute(params![file_id, c.kind, c.symbol, c.begin_line, c.end_line, sha, mtime, text, tokens, part, parts])?;
+                written += 1;
+            }
+        }
+    }
+    // всё, что не сопоставилось — устарело
+    for id in old.into_values().flatten() {
+        del.execute(params![id])?;
+        removed += 1;
+    }
+    Ok((written, removed, kept))
+}
+
+// Простая версия чанкинга: один тег → один чанк
+#[derive(Debug)]
+pub(crate) struct ChunkSpec {
+    pub(crate) kind: String,
+    pub(crate) symbol: Option<String>,
+    pub(crate) begin_line: i64, // 1-based
+    pub(crate) end_line: i64,   // inclusive
+    pub(crate) part: Option<(i64, i64)>, // (номер с 1, всего) — если символ порезан на части
+}
+
+/// Синтаксис комментариев языка: строчные префиксы и пары (открытие, закрытие) блочных
+type CommentSyntax = (&'static [&'static str], &'static [(&'static str, &'static str)]);
+
+fn comment_syntax(lang: &str) -> Option<CommentSyntax> {
+    match lang {
+        "rust" | "c" | "cpp" | "ts" | "js" => Some((&["//"], &[("/*", "*/")])),
+        "python" => Some((&["#"], &[("\"\"\"", "\"\"\""), ("'''", "'''")])),
+        "lua" => Some((&["--"], &[("--[[", "]]")])),
+        _ => None,
+    }
+}
+
+/// Блоки комментариев, занимающих строку целиком: подряд идущие строчные комментарии или
+/// один блочный (вместе с пустыми строками внутри него). Строки (begin, end), 1-based inclusive.
+/// Хвостовые комментарии после кода не считаются; shebang в первой строке — тоже.
+pub(crate) fn comment_blocks(text: &str, lang: &str) -> Vec<(i64, i64)> {
+    let Some((line_prefixes, blocks)) = comment_syntax(lang) else { return Vec::new() };
+    let mut out: Vec<(i64, i64)> = Vec::new();
+    let mut cur: Option<(i64, i64)> = None;
+    let mut close: Option<&str> = None;
+    for (i, line) in text.lines().enumerate() {
+        let n = i as i64 + 1;
+        let t = line.trim();
+        if let Some(end) = close {
+            if t.contains(end) { close = None; }
+            if let Some(c) = cur.as_mut() { c.1 = n; }
+            continue;
+        }
+        // блочный комментарий проверяем раньше строчного: "--[[" начинается с "--"
+        let is_comment = if let Some((open, end)) = blocks.iter().find(|(open, _)| t.starts_with(open)) {
+            if !t[open.len()..].contains(end) { close = Some(end); }
+            true
+        } else {
+            !(n == 1 && t.starts_with("#!/")) && line_prefixes.iter().any(|p| t.starts_with(p))
+        };
+        if is_comment {
+            let c = cur.get_or_insert((n, n));
+            c.1 = n;
+        } else if let Some(c) = cur.take() {
+            out.push(c);
+        }
+    }
+    out.extend(cur);
+    out
+}
+
+/// Начальный блок комментариев файла (лицензия, описание модуля, докстринг): строки
+/// (begin, end), 1-based inclusive. Пустые строки между группами комментариев допускаются,
+/// shebang пропускается; заканчивается на первой строке кода.
+pub(crate) fn leading_comment(text: &str, lang: &str) -> Option<(i64, i64)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let blank = |from: i64, to: i64| (from..to).all(|n| {
+        let t = lines[(n - 1) as usize].trim();
+        t.is_empty() || (n == 1 && t.starts_with("#!/"))
+    });
+    let mut header: Option<(i64, i64)> = None;
+    for (b, e) in comment_blocks(text, lang) {
+        let prev_end = header.map_or(0, |h| h.1);
+        if !blank(prev_end + 1, b) { break; }
+        header = Some((header.map_or(b, |h| h.0), e));
+    }
+    header
+}
+
+/// Самостоятельные комментарии от `min_lines` строк как kind='comment' чанки. symbol — ближайший
+/// символ: объемлющий (самый узкий), иначе следующий за комментарием (doc-комментарий к нему),
+/// иначе предыдущий. Начальный комментарий файла пропускаем, если он уже ушёл в file_header.
+pub(crate) fn comment_chunks(specs: &[ChunkSpec], text: &str, lang: &str, min_lines: usize, skip_header: bool) -> Vec<ChunkSpec> {
+    let header = if skip_header { leading_comment(text, lang) } else { None };
+    let symbols: Vec<&ChunkSpec> = specs.iter().filter(|s| s.symbol.is_some()).collect();
+    comment_blocks(text, lang).into_iter()
+        .filter(|&(b, e)| e - b + 1 >= min_lines as i64)
+        .filter(|&(b, e)| header.is_none_or(|(hb, he)| b > he || e < hb))
+        .map(|(b, e)| {
+            let enclosing = symbols.iter()
+                .filter(|s| s.begin_line <= b && s.end_line >= e)
+                .min_by_key(|s| s.end_line - s.begin_line);
+            let next = || symbols.iter().filter(|s| s.begin_line > e).min_by_key(|s| s.begin_line);
+            let prev = || symbols.iter().filter(|s| s.end_line < b).max_by_key(|s| s.end_line);
+            let symbol = enclosing.or_else(next).or_else(prev).and_then(|s| s.symbol.clone());
+            ChunkSpec { kind: "comment".into(), symbol, begin_line: b, end_line: e, part: None }
+        })
+        .collect()
+}
+
+/// Лямбды C++ внутри чанков-функций: `[захват](параметры) … {` и парная `}`.
+/// Эвристика по тексту — ctags лямбд не видит; `[` после идентификатора/`]`/`)` — индексация, не лямбда
+pub(crate) fn lambda_chunks(specs: &[ChunkSpec], text: &str, lang: &str, min_lines: usize) -> Vec<ChunkSpec> {
+    if lang != "cpp" { return Vec::new(); }
+    let re = Regex::new(r"(?mx)
+        (?: ^ | [=(,{;:?!&|] | return )\s*
+        \[ [^\[\];]* \] \s*
+        (?: \( (?: [^()] | \( [^()]* \) )* \) \s* )?
+        (?: (?: mutable | constexpr | noexcept ) \s* )*
+        (?: -> [^{;]+? )?
+        \{").unwrap();
+    let line_of = |pos: usize| text[..pos].bytes().filter(|&b| b == b'\n').count() as i64 + 1;
+    let functions: Vec<&ChunkSpec> = specs.iter().filter(|s| s.kind == "function" && s.symbol.is_some()).collect();
+    let literals = literal_spans(text);
+    let mut out = Vec::new();
+    for m in re.find_iter(text) {
+        // "[x] {" внутри строки или комментария — не лямбда
+        let bracket = m.start() + m.as_str().find('[').unwrap_or(0);
+        if literals.iter().any(|&(s, e)| s <= bracket && bracket <= e) { continue; }
+        let open = m.end() - 1;
+        let Some(close) = matching_brace(text, open) else { continue };
+        let (b, e) = (line_of(bracket), line_of(close));
+        if e - b + 1 < min_lines as i64 { continue; }
+        // только внутри функций: на уровне namespace лямбда — это инициализатор, а не логика «в глубине»
+        let Some(f) = functions.iter()
+            .filter(|s| s.begin_line <= b && s.end_line >= e && (s.begin_line, s.end_line) != (b, e))
+            .min_by_key(|s| s.end_line - s.begin_line)
+        else { continue };
+        out.push(ChunkSpec { kind: "lambda".into(), symbol: f.symbol.clone(), begin_line: b, end_line: e, part: None });
+    }
+    out
+}
+
+/// Позиция `}`, парной к `{` в `open`; строки, символьные литералы и комментарии пропускаем
+fn matching_brace(text: &str, open: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let (mut depth, mut i) = (0usize, open);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 { return Some(i); }
+            }
+            _ => if let Some(end) = literal_end(bytes, i) { i = end; },
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Строка, символьный литерал или комментарий C/C++, начинающийся в `i`: позиция его последнего
+/// байта (незакрытый — до конца текста); None — литерала здесь нет
+fn literal_end(bytes: &[u8], i: usize) -> Option<usize> {
+    match bytes[i] {
+        q @ (b'"' | b'\'') => {
+            let mut j = i + 1;
+            while j < bytes.len() && bytes[j] != q {
+                if bytes[j] == b'\\' { j += 1; }
+                j += 1;
+            }
+            Some(j)
+        }
+        b'/' if bytes.get(i + 1) == Some(&b'/') => {
+            let mut j = i;
+            while j < bytes.len() && bytes[j] != b'\n' { j += 1; }
+            Some(j)
+        }
+        b'/' if bytes.get(i + 1) == Some(&b'*') => {
+            let mut j = i + 2;
+            while j + 1 < bytes.len() && !(bytes[j] == b'*' && bytes[j + 1] == b'/') { j += 1; }
+            Some(j + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Диапазоны байтов (begin, end включительно) всех литералов и комментариев текста
+fn literal_spans(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let (mut out, mut i) = (Vec::new(), 0);
+    while i < bytes.len() {
+        if let Some(end) = literal_end(bytes, i) {
+            out.push((i, end));
+            i = end;
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Языки, где блок заканчивается возвратом отступа, а не скобкой
+pub(crate) fn uses_indent_blocks(lang: &str) -> bool {
+    matches!(lang, "python")
+}
+
+/// Конец блока по отступу: первая непустая строка с отступом ≤ строки определения
+/// закрывает блок; возвращаем последнюю непустую строку перед ней (1-based, inclusive).
+/// Строки внутри многострочной строки `"""`/`'''` (докстринг без отступа) блок не закрывают
+pub(crate) fn indent_end_line(text: &str, begin: i64) -> i64 {
+    let indent = |l: &str| l.len() - l.trim_start().len();
+    let lines: Vec<&str> = text.lines().collect();
+    let Some(def) = lines.get((begin - 1).max(0) as usize) else { return begin };
+    let base = indent(def);
+    let mut end = begin;
+    let mut open = triple_quote_after(def, None);
+    for (idx, l) in lines.iter().enumerate().skip(begin as usize) {
+        let in_string = open.is_some();
+        open = triple_quote_after(l, open);
+        if in_string {
+            end = idx as i64 + 1;
+            continue;
+        }
+        if l.trim().is_empty() { continue; }
+        // следующий def/декоратор на том же уровне закрывает блок
+        if indent(l) <= base { break; }
+        end = idx as i64 + 1;
+    }
+    end
+}
+
+/// Тройная кавычка, открытая после строки `l`; `open` — открытая до неё
+fn triple_quote_after(l: &str, mut open: Option<&'static str>) -> Option<&'static str> {
+    let mut rest = l;
+    loop {
+        let next = match open {
+            Some(q) => rest.find(q).map(|p| (p, None)),
+            None => ["\"\"\"", "'''"].into_iter().filter_map(|q| rest.find(q).map(|p| (p, Some(q)))).min(),
+        };
+        let Some((p, now_open)) = next else { return open };
+        rest = &rest[p + 3..];
+        open = now_open;
+    }
+}
+
+/// Объявление без тела: строки чанка до первой `{` или `;` (у языков с отступами — до `:` в конце
+/// строки), последняя обрезана по этот символ. Комментарии в начале чанка и декораторы сохраняются;
+/// конца объявления нет в первых 10 строках — только первая строка после комментариев.
+pub(crate) fn declaration(text: &str, lang: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let skip = comment_blocks(text, lang).first().filter(|(b, _)| *b == 1).map_or(0, |&(_, e)| e as usize);
+    let mut out: Vec<&str> = lines[..skip.min(lines.len())].to_vec();
+    let body = &lines[skip.min(lines.len())..];
+    for (i, l) in body.iter().take(10).enumerate() {
+        let end = if uses_indent_blocks(lang) {
+            l.trim_end().ends_with(':').then(|| l.trim_end().len())
+        } else {
+            l.find(['{', ';']).map(|p| p + 1)
+        };
+        if let Some(end) = end {
+            out.extend(&body[..i]);
+            out.push(&l[..end]);
+            return out.join("\n");
+        }
+    }
+    out.extend(body.first());
+    out.join("\n")
+}
+
+/// Виды тегов ctags по языкам → наши канонические (function|class|namespace|enum|union|typedef)
+const KINDS: &[(&str, &[(&str, &str)])] = &[
+    ("C", &[("function", "function"), ("prototype", "function"), ("member", "function"), ("struct", "class"),
+            ("enum", "enum"), ("union", "union"), ("typedef", "typedef")]),
+    ("C++", &[("function", "function"), ("prototype", "function"), ("member", "function"), ("class", "class"),
+              ("struct", "class"), ("namespace", "namespace"), ("enum", "enum"), ("union", "union"), ("typedef", "typedef")]),
+    ("Rust", &[("function", "function"), ("method", "function"), ("struct", "class"), ("implementation", "class"),
+               ("interface", "class"), ("module", "namespace"), ("enum", "enum"), ("typedef", "typedef")]),
+    ("Python", &[("function", "function"), ("member", "function"), ("class", "class")]),
+    ("TypeScript", &[("function", "function"), ("method", "function"), ("class", "class"), ("interface", "class"),
+                     ("namespace", "namespace"), ("enum", "enum"), ("alias", "typedef")]),
+    ("JavaScript", &[("function", "function"), ("method", "function"), ("generator", "function"), ("class", "class")]),
+    ("Lua", &[("function", "function")]),
+];
+
+/// Канонический вид тега; None — тег чанкингу не нужен (переменные, поля, импорты, языки без
+/// таблицы). Пустой язык (теги, сохранённые без него) читаем как C++ — так работал index раньше.
+pub(crate) fn canonical_kind(language: &str, kind: &str) -> Option<&'static str> {
+    let language = if language.is_empty() { "C++" } else { language };
+    let (_, kinds) = KINDS.iter().find(|(l, _)| *l == language)?;
+    kinds.iter().find(|(k, _)| *k == kind).map(|(_, c)| *c)
+}
+
+pub(crate) fn build_chunks_v1(tags: &[TagSpan], total_lines: i64, indent_src: Option<&str>) -> Vec<ChunkSpec> {
+    let mut out = Vec::new();
+    if tags.is_empty() {
+        // нет тегов — пока пропускаем (можно добавить fallback блоки позже)
+        return out;
+    }
+    for (i, t) in tags.iter().enumerate() {
+        let begin = t.line;
+        let end = if let Some(e) = t.end_line { e }
+                  else if let Some(text) = indent_src { indent_end_line(text, begin) }
+                  else if let Some(next) = tags.get(i + 1) {
+                      (next.line - 1).max(begin)
+                  } else {
+                      total_lines
+                  };
+        let sym = if let Some(scope) = &t.scope {
+            Some(format!("{scope}::{}", t.name))
+        } else {
+            Some(t.name.clone())
+        };
+        let kind = canonical_kind(&t.language, &t.kind).unwrap_or("block").to_string();
+
+        // защита от мусора
+        if begin <= 0 || end < begin { continue; }
+
+        out.push(ChunkSpec {
+            kind,
+            symbol: sym,
+            begin_line: begin,
+            end_line: end,
+            part: None,
+        });
+    }
+    out
+}
+
+/// Грубая оценка токенов: ~4 символа на токен (токенизатора в зависимостях нет)
+pub(crate) fn estimate_tokens(s: &str) -> i64 {
+    (s.chars().count() as i64 + 3) / 4
+}
+
+/// Части одного чанка по диапазонам строк: kind/symbol общие, part — номер из общего числа.
+/// Чанк, уже бывший частью (окно --max-chunk-lines), номер сохраняет.
+fn into_parts(c: ChunkSpec, ranges: Vec<(i64, i64)>) -> impl Iterator<Item = ChunkSpec> {
+    let n = ranges.len() as i64;
+    ranges.into_iter().enumerate().map(move |(i, (b, e))| ChunkSpec {
+        kind: c.kind.clone(),
+        symbol: c.symbol.clone(),
+        begin_line: b,
+        end_line: e,
+        part: if n > 1 && c.part.is_none() { Some((i as i64 + 1, n)) } else { c.part },
+    })
+}
+
+/// --max-chunk-lines: чанк длиннее max строк режем на окна по max строк, соседние окна
+/// делят `overlap` строк — код на границе окна попадает в оба. Последнее окно доходит до конца чанка.
+fn split_by_lines(specs: Vec<ChunkSpec>, max: usize, overlap: usize) -> Vec<ChunkSpec> {
+    // validate() гарантирует overlap < max
+    let max = max as i64;
+    let step = max - overlap as i64;
+    let mut out = Vec::new();
+    for c in specs {
+        let mut ranges = vec![];
+        let mut begin = c.begin_line;
+        loop {
+            let end = (begin + max - 1).min(c.end_line);
+            ranges.push((begin, end));
+            if end >= c.end_line { break; }
+            begin += step;
+        }
+        out.extend(into_parts(c, ranges));
+    }
+    out
+}
+
+/// --chunk-by-tokens: режем каждый чанк по границам строк на части ≈ target токенов.
+/// Части сохраняют kind/symbol исходного чанка; строка длиннее target остаётся целой.
+fn split_by_tokens(specs: Vec<ChunkSpec>, text: &str, target: usize) -> Vec<ChunkSpec> {
+    let target = target.max(1) as i64;
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out = Vec::new();
+    for c in specs {
+        let mut ranges = vec![];
+        let mut begin = c.begin_line;
+        let mut acc = 0i64;
+        for ln in c.begin_line..=c.end_line {
+            acc += estimate_tokens(lines.get((ln - 1) as usize).copied().unwrap_or("")) + 1; // +1 — перевод строки
+            if acc >= target && ln < c.end_line {
+                ranges.push((begin, ln));
+                begin = ln + 1;
+                acc = 0;
+            }
+        }
+        ranges.push((begin, c.end_line));
+        out.extend(into_parts(c, ranges));
+    }
+    out
+}
+
+
+pub(crate) fn sha256_str(s: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let mut h = Sha256::new();
+    h.update(s.as_bytes());
+    format!("{:x}", h.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(begin_line: i64, end_line: i64) -> ChunkSpec {
+        ChunkSpec { kind: "function".into(), symbol: Some("f".into()), begin_line, end_line, part: None }
+    }
+
+    type Part = Option<(i64, i64)>;
+
+    fn ranges(specs: &[ChunkSpec]) -> Vec<(i64, i64, Part)> {
+        specs.iter().map(|s| (s.begin_line, s.end_line, s.part)).collect()
+    }
+
+    #[test]
+    fn split_by_lines_overlaps_at_window_edges() {
+        let parts = split_by_lines(vec![spec(1, 10)], 4, 1);
+        assert_eq!(ranges(&parts), vec![(1, 4, Some((1, 3))), (4, 7, Some((2, 3))), (7, 10, Some((3, 3)))]);
+        assert!(parts.iter().all(|p| p.symbol.as_deref() == Some("f")));
+    }
+
+    #[test]
+    fn split_by_lines_short_tail_window() {
+        // последнее окно короче max и всё равно доходит до конца чанка
+        assert_eq!(ranges(&split_by_lines(vec![spec(10, 14)], 4, 1)), vec![(10, 13, Some((1, 2))), (13, 14, Some((2, 2)))]);
+        assert_eq!(ranges(&split_by_lines(vec![spec(1, 8)], 4, 0)), vec![(1, 4, Some((1, 2))), (5, 8, Some((2, 2)))]);
+    }
+
+    #[test]
+    fn split_by_lines_keeps_chunks_that_fit() {
+        assert_eq!(ranges(&split_by_lines(vec![spec(3, 6)], 4, 2)), vec![(3, 6, None)]);
+    }
+
+    #[test]
+    fn split_by_tokens_cuts_at_line_boundaries() {
+        // строка из 8 символов ≈ 2 токена + перевод строки = 3
+        let text = "aaaaaaaa\n".repeat(5);
+        assert_eq!(ranges(&split_by_tokens(vec![spec(1, 5)], &text, 6)),
+            vec![(1, 2, Some((1, 3))), (3, 4, Some((2, 3))), (5, 5, Some((3, 3)))]);
+        assert_eq!(ranges(&split_by_tokens(vec![spec(1, 5)], &text, 100)), vec![(1, 5, None)]);
+    }
+
+    #[test]
+    fn split_by_tokens_keeps_long_line_whole() {
+        let text = format!("short\n{}\nshort\n", "x".repeat(400));
+        assert_eq!(ranges(&split_by_tokens(vec![spec(1, 3)], &text, 10)),
+            vec![(1, 2, Some((1, 2))), (3, 3, Some((2, 2)))]);
+    }
+
+    #[test]
+    fn split_by_tokens_keeps_existing_part_number() {
+        let text = "aaaaaaaa\n".repeat(4);
+        let part = ChunkSpec { part: Some((2, 3)), ..spec(1, 4) };
+        assert_eq!(ranges(&split_by_tokens(vec![part], &text, 6)), vec![(1, 2, Some((2, 3))), (3, 4, Some((2, 3)))]);
+    }
+
+    #[test]
+    fn comment_blocks_line_and_block_comments() {
+        let text = "// a\n// b\nint x; // хвост\n/* c\n\n   d */\nint y;\n";
+        assert_eq!(comment_blocks(text, "cpp"), vec![(1, 2), (4, 6)]);
+        // --[[ блочный, хотя начинается с --
+        assert_eq!(comment_blocks("--[[ a\nb ]]\n-- c\nx = 1\n", "lua"), vec![(1, 3)]);
+        assert_eq!(comment_blocks("#!/usr/bin/env python\n# a\nx = 1\n", "python"), vec![(2, 2)]);
+        assert!(comment_blocks("// a\n", "other").is_empty());
+    }
+
+    #[test]
+    fn comment_blocks_unterminated_block_runs_to_eof() {
+        // как у компилятора: без */ комментарием остаётся весь хвост файла
+        let text = "int a;\n/* начало\nint b;\n\nint c;\n";
+        assert_eq!(comment_blocks(text, "c"), vec![(2, 5)]);
+        assert_eq!(leading_comment("/* лицензия\nint b;\n", "c"), Some((1, 2)));
+    }
+
+    #[test]
+    fn leading_comment_spans_groups_until_code() {
+        let text = "#!/usr/bin/env python\n# License\n\n\"\"\"Модуль.\n\nПодробно.\n\"\"\"\nimport os\n# не заголовок\n";
+        assert_eq!(leading_comment(text, "python"), Some((2, 7)));
+        assert_eq!(leading_comment("use x;\n// doc\nfn f() {}\n", "rust"), None);
+    }
+
+    #[test]
+    fn matching_brace_skips_literals_and_comments() {
+        let text = r#"{ s = "}"; c = '}'; // }
+ /* } */ x = '\''; }"#;
+        assert_eq!(matching_brace(text, 0), Some(text.len() - 1));
+        assert_eq!(matching_brace("{ \"", 0), None);
+    }
+
+    #[test]
+    fn lambda_chunks_ignore_braces_in_string_literals() {
+        let text = r#"void f() {
+    auto s = "= [a] {";
+    auto g = [&](int x) {
+        log("}");
+        return x;
+    };
+}
+"#;
+        let lambdas = lambda_chunks(&[spec(1, 7)], text, "cpp", 2);
+        assert_eq!(ranges(&lambdas), vec![(3, 6, None)]);
+        assert_eq!(lambdas[0].kind, "lambda");
+        assert_eq!(lambdas[0].symbol.as_deref(), Some("f"));
+        assert!(lambda_chunks(&[spec(1, 7)], text, "c", 2).is_empty());
+    }
+
+    #[test]
+    fn lambda_chunks_skip_indexing_and_short_lambdas() {
+        let text = "void f() {\n    v[i] = 1;\n    auto h = [](int a) { return a; };\n}\n";
+        assert!(lambda_chunks(&[spec(1, 4)], text, "cpp", 2).is_empty());
+        assert_eq!(ranges(&lambda_chunks(&[spec(1, 4)], text, "cpp", 1)), vec![(3, 3, None)]);
+    }
+
+    #[test]
+    fn overlap_not_less_than_max_is_rejected() {
+        let s = Strategy { max_chunk_lines: Some(4), overlap_lines: 4, ..Default::default() };
+        assert!(s.validate().is_err());
+        let s = Strategy { max_chunk_lines: Some(4), overlap_lines: 3, ..Default::default() };
+        assert!(s.validate().is_ok());
+    }
+
+    #[test]
+    fn declaration_stops_at_body() {
+        let text = "/// Документация\n/// в две строки\npub fn run(\n    a: i32,\n) -> Result<()> {\n    body();\n}";
+        assert_eq!(declaration(text, "rust"), "/// Документация\n/// в две строки\npub fn run(\n    a: i32,\n) -> Result<()> {");
+        assert_eq!(declaration("int f(int x);\nint g();", "cpp"), "int f(int x);");
+    }
+
+    #[test]
+    fn declaration_python_keeps_decorators() {
+        let text = "@app.route(\"/x\")\ndef handler(req: Request):\n    return 1\n";
+        assert_eq!(declaration(text, "python"), "@app.route(\"/x\")\ndef handler(req: Request):");
+    }
+
+    #[test]
+    fn declaration_without_terminator_is_first_line() {
+        assert_eq!(declaration("[package]\nname = \"x\"", "other"), "[package]");
+    }
+
+    #[test]
+    fn indent_end_line_stops_before_decorator() {
+        let text = "def first():\n    return 1\n\n@app.route(\"/x\")\ndef second():\n    return 2\n";
+        assert_eq!(indent_end_line(text, 1), 2);
+        assert_eq!(indent_end_line(text, 5), 6);
+    }
+
+    #[test]
+    fn indent_end_line_nested_blocks() {
+        let text = "class A:\n    def f(self):\n        if x:\n            y()\n\n    def g(self):\n        pass\nz = 1\n";
+        assert_eq!(indent_end_line(text, 1), 7);
+        assert_eq!(indent_end_line(text, 2), 4);
+        assert_eq!(indent_end_line(text, 6), 7);
+    }
+
+    #[test]
+    fn indent_end_line_dedented_docstring() {
+        let text = "def f():\n    \"\"\"Doc\nDedented line\n\n    \"\"\"\n    return 1\ndef g():\n    '''one-liner'''\n    pass\n";
+        assert_eq!(indent_end_line(text, 1), 6);
+        assert_eq!(indent_end_line(text, 7), 9);
+    }
+}
@@ -1,49 +1,104 @@
 use anyhow::{bail, Context, Result};
+use clap::Args;
 use async_openai::{
-    types::responses::{ContentType, CreateResponseArgs, Input, InputContent, InputItem, InputMessageArgs, InputMessageType, InputText, Role, Usage}, Client
+    types::responses::{CreateResponseArgs, Input, InputContent, InputItem, InputMessageArgs, InputMessageType, Response, Role}
 };
 use regex::Regex;
-use rusqlite::{fallible_streaming_iterator::FallibleStreamingIterator, params, Connection};
-use std::{fs, path::{Path, PathBuf}};
-use time::OffsetDateTime;
-
-use crate::{commands::extract_output_text, db::open_db, fs as ufs, state::ProjectState};
-
-pub async fn run(
-    symbol: Option<String>,
-    file: Option<String>,
-    lines: Option<String>,
-    model: String,
-    max_output: u32,
-    window: u32,
-) -> Result<()> {
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, fs, path::{Path, PathBuf}, process::Command};
+
+use crate::{commands::{chunk, extract_output_text, index, output_text}, db::open_db, fs as ufs, llm::{self, CallOpts, GenOpts}, state::ProjectState};
+
+#[derive(Args, Debug)]
+pub struct ExplainArgs {
+    #[arg(long)] pub symbol: Option<String>,    // напр. "net::TlsClient::handshake"
+    #[arg(long)] pub file: Option<String>,      // относительный путь
+    #[arg(long)] pub lines: Option<String>,     // "A:B" или "N" — символ, содержащий строку N
+    #[arg(long, default_value="gpt-4.1-mini")] pub model: String,   // или псевдоним из GPTCLI_MODEL_ALIASES
+    #[arg(long, default_value_t=900)] pub max_output: u32,
+    #[arg(long, default_value_t=15)] pub window: u32,   // контекст ±N строк
+    /// Печатать полный JSON ответа Responses API вместо текста; с --json — полем raw_response
+    #[arg(long)] pub raw_response: bool,
+    /// Добавить [INSTANTIATIONS]: конкретные места использования шаблона
+    #[arg(long)] pub instantiations: bool,
+    /// Вывести JSON: цель, достаточность данных по секциям, ответ
+    #[arg(long)] pub json: bool,
+    /// Приложить целиком заголовок цели и файлы с определениями вызываемых функций ([FILES])
+    #[arg(long)] pub attach_files: bool,
+    /// Сколько файлов с callee прикладывать максимум
+    #[arg(long, default_value_t=3)] pub attach_max: usize,
+    /// Бюджет [FILES] в токенах (оценка: символы/4); не влезающие файлы пропускаются
+    #[arg(long, default_value_t=8000)] pub attach_budget: usize,
+    /// Объяснять файл цели в состоянии git-ревизии (git show REV:path); прочие секции — по индексу
+    #[arg(long)] pub rev: Option<String>,
+    /// Для скриптов: код выхода ≠ 0, если ответ пустой, отказ модели или фактов недостаточно
+    #[arg(long)] pub strict: bool,
+    #[command(flatten)] pub gen_opts: GenOpts,
+    #[command(flatten)] pub call: CallOpts,
+}
+
+pub async fn run(a: ExplainArgs) -> Result<()> {
+    let ExplainArgs { symbol, file, lines, model, max_output, window, raw_response, instantiations, json,
+        attach_files, attach_max, attach_budget, rev, strict, gen_opts, call } = a;
     let root = ufs::detect_project_root()?;
     let st   = ProjectState::load(&root)?;
-    let ns   = st.namespace.clone();
+    let ns   = st.active_namespace();
     let conn = open_db(&root)?;
 
-    // 1) Определяем цель
-    let tgt = resolve_target(&conn, &root, &ns, symbol.as_deref(), file.as_deref(), lines.as_deref())?
-        .context("не удалось определить цель (symbol/lines)")?;
+    // 1) Определяем цель и текст её файла (с диска или из ревизии)
+    let (tgt, src) = match rev.as_deref() {
+        None => {
+            let tgt = resolve_target(&conn, &root, &ns, symbol.as_deref(), file.as_deref(), lines.as_deref())?
+                .context("не удалось определить цель (symbol/lines)")?;
+            let src = ufs::read_text_sanitized(&root.join(&tgt.path))?;
+            (tgt, src)
+        }
+        Some(r) => resolve_at_rev(&conn, &root, &ns, r, symbol.as_deref(), file.as_deref(), lines.as_deref())?,
+    };
 
     // 2) Собираем контекстные секции
-    let decl_def   = section_decl_def(&root, &tgt, window as i64)?;
+    let decl_def   = section_decl_def(&src, &tgt, window as i64);
     let class_type = section_class_type(&conn, &root, &ns, &tgt, window as i64)?;
-    let pp         = section_preproc(&root, &tgt, 30)?;
-    let callees    = section_callees(&conn, &root, &ns, &tgt, 12)?;
-    let usage      = section_usage_examples(&conn, &ns, &tgt.name, 3)?;
-    let comments   = section_comments(&root, &tgt, 12)?;
+    let pp         = section_preproc(&src, &tgt, 30);
+    let callees    = section_callees(&conn, &src, &ns, &tgt, 12)?;
+    let external   = section_external_deps(&conn, &src, &ns, &tgt, 24)?;
+    let usage      = section_usage_examples(&conn, &ns, &tgt, 3)?;
+    let comments   = section_comments(&src, &tgt, 12);
+
+    // (секция, собранный текст) — для --json: какие секции остались без данных
+    let mut collected: Vec<(&str, String)> = vec![
+        ("decl_def", decl_def.clone()), ("class_type", class_type.clone()), ("preprocessor", pp.clone()),
+        ("callees", callees.clone()), ("external_deps", external.clone()),
+        ("usage", usage.clone()), ("comments", comments.clone()),
+    ];
+
+    // опциональные секции — вставляются перед [ASK]
+    let mut extra = String::new();
+    if instantiations {
+        let inst = section_instantiations(&conn, &ns, &tgt, 4)?;
+        extra.push_str(&format!("[INSTANTIATIONS]\n{inst}\n\n"));
+        collected.push(("instantiations", inst));
+    }
+    if attach_files {
+        let files = section_files(&conn, &root, &ns, &tgt, &src, attach_max, attach_budget)?;
+        extra.push_str(&format!("[FILES]\n{files}\n\n"));
+        collected.push(("files", files));
+    }
 
     // 3) Формируем секционный prompt
     let system = "Ты — senior C/C++ reviewer. Объясняй по фактам, кратко и структурированно. Не выдумывай.
 Структура ответа: Назначение; Как работает; Ввод/вывод и инварианты; Ошибки/исключения;
-Потоки/память/реентерабельность; Сложность/перф; Примеры применения; Риски/краевые случаи.";
+Потоки/память/реентерабельность; Сложность/перф; Примеры применения; Риски/краевые случаи;
+[CALL EXAMPLE] — минимальный компилируемый пример вызова цели одним блоком кода: строго по signature
+и местам из [USAGE], без выдуманных функций; чего не хватает (конструктор, аргументы) — пометь комментарием.";
 
     let facts = format!(r#"[TARGET]
 name: {name}
 file: {path}:{bl}-{el}
 kind: {kind}
 signature: {sig}
+type: {ty}
 
 [DECL/DEF]
 {decl_def}
@@ -57,35 +112,97 @@ signature: {sig}
 [CALLEES]
 {callees}
 
+[EXTERNAL DEPS]
+{external}
+
 [USAGE]
 {usage}
 
 [COMMENTS]
 {comments}
 
-[ASK]
-Дай обзор по структуре из system. Если данных недостаточно — явно отметь «не найдено» в соответствующих секциях."#,
+{extra}[ASK]
+Дай обзор по структуре из system. Если данных недостаточно — явно отметь «не найдено» в соответствующих секциях.
+На какие внешние API (stdlib, сторонние библиотеки) из [EXTERNAL DEPS] опирается цель — отметь там, где это важно.
+Закончи разделом [CALL EXAMPLE]."#,
         name=tgt.fqn.as_deref().unwrap_or(&tgt.name),
-        path=tgt.path, bl=tgt.begin_line, el=tgt.end_line,
-        kind=tgt.kind, sig=tgt.signature.unwrap_or_default(),
-        decl_def=decl_def, class_type=class_type, pp=pp, callees=callees,
-        usage=usage, comments=comments
+        path=rev.as_ref().map(|r| format!("{r}:{}", tgt.path)).unwrap_or_else(|| tgt.path.clone()), bl=tgt.begin_line, el=tgt.end_line,
+        kind=tgt.kind, sig=tgt.signature.as_deref().unwrap_or_default(),
+        ty=tgt.type_ref.as_deref().and_then(type_name).unwrap_or("—"),
+        decl_def=decl_def, class_type=class_type, pp=pp, callees=callees, external=external,
+        usage=usage, comments=comments, extra=extra
     );
 
-    // 4) Запрос к OpenAI (Responses API через async-openai) + лог в /tmp
-    let (text, usage, req_path, resp_path) = call_openai(model, max_output, &facts, system).await?;
+    // 4) Запрос к OpenAI (Responses API через async-openai) + лог запроса/ответа
+    let Some((resp, req_path, resp_path)) = call_openai(&root, model, max_output, &facts, system, &gen_opts, &call).await?
+    else { return Ok(()) };
+    let text = extract_output_text(&resp);
 
-    println!("{text}\n");
-    eprintln!("— raw request:  {req_path}");
-    eprintln!("— raw response: {resp_path}");
+    if json {
+        let sections: serde_json::Map<String, serde_json::Value> = collected.iter()
+            .map(|(name, body)| (name.to_string(), serde_json::json!({ "sufficient": section_sufficient(body) })))
+            .collect();
+        let mut out = serde_json::json!({
+            "target": {
+                "name": tgt.name, "fqn": tgt.fqn, "kind": tgt.kind,
+                "path": tgt.path, "rev": rev, "begin_line": tgt.begin_line, "end_line": tgt.end_line,
+                "signature": tgt.signature, "type": tgt.type_ref.as_deref().and_then(type_name),
+            },
+            "sections": sections,
+            "call_example": extract_call_example(&text),
+            "answer": text,
+        });
+        // --raw-response: весь ответ объектом рядом с извлечённым текстом, а не строкой вместо него
+        if raw_response { out["raw_response"] = serde_json::to_value(&resp)?; }
+        println!("{}", serde_json::to_string_pretty(&out)?);
+    } else if raw_response {
+        // --raw-response: весь JSON ответа вместо извлечённого текста
+        println!("{}\n", serde_json::to_string_pretty(&resp)?);
+    } else {
+        println!("{text}\n");
+    }
+    eprintln!("— raw request:  {}", req_path.display());
+    eprintln!("— raw response: {}", resp_path.display());
+
+    if strict {
+        // вывод уже напечатан — скрипт видит и ответ, и причину отказа
+        if let Some(r) = llm::refusal(&resp) {
+            bail!("strict: model refused: {r}");
+        }
+        if output_text(&resp).is_none() {
+            bail!("strict: empty answer");
+        }
+        // без кода цели (то же, что sufficient=false у decl_def в --json) ответ — догадка
+        if collected.iter().any(|(name, body)| *name == "decl_def" && !section_sufficient(body)) {
+            bail!("strict: insufficient data (no declaration/definition found for the target)");
+        }
+    }
     Ok(())
 }
 
+/// Блок кода из раздела [CALL EXAMPLE] ответа (без ограждений ```); None — модель его не дала
+fn extract_call_example(answer: &str) -> Option<String> {
+    let start = answer.find("CALL EXAMPLE")?;
+    let rest = &answer[start..];
+    let open = rest.find("```")?;
+    let body = &rest[open + 3..];
+    // после ``` идёт язык (```cpp) до конца строки
+    let body = &body[body.find('\n')? + 1..];
+    let end = body.find("```").unwrap_or(body.len());
+    let code = body[..end].trim_end();
+    (!code.trim().is_empty()).then(|| code.to_string())
+}
+
+/// Секция без данных — "—" или пусто; известно до вызова модели
+fn section_sufficient(body: &str) -> bool {
+    let t = body.trim();
+    !t.is_empty() && t != "—"
+}
+
 /* ---------- target resolve ---------- */
 
 #[derive(Debug, Clone)]
 struct Target {
-    file_id: i64,
     path: String,
     name: String,             // короткое имя
     fqn: Option<String>,      // scope::name
@@ -93,6 +210,16 @@ struct Target {
     begin_line: i64,
     end_line: i64,
     signature: Option<String>,
+    type_ref: Option<String>, // typeref из ctags: возвращаемый тип функции / тип поля
+}
+
+/// ctags typeref "typename:int" → "int"; прочие ("struct:Foo") — как есть
+fn type_name(type_ref: &str) -> Option<&str> {
+    let ty = match type_ref.split_once(':') {
+        Some(("typename", t)) => t,
+        _ => type_ref,
+    };
+    (!ty.is_empty()).then_some(ty)
 }
 
 fn resolve_target(
@@ -105,9 +232,9 @@ fn resolve_target(
 ) -> Result<Option<Target>> {
     if let Some(sym) = symbol {
         // FQN или короткое имя
-        let (scope, name) = split_fqn(sym);
+        let (_, name) = split_fqn(sym);
         let mut q = conn.prepare(
-            "SELECT t.rowid, f.path, t.name, t.kind, t.line, COALESCE(t.end_line,0), t.scope, t.signature
+            "SELECT t.file_id, f.path, t.name, t.kind, t.line, COALESCE(t.end_line,0), t.scope, t.signature, t.type_ref
                FROM tags t
                JOIN files f ON f.id=t.file_id
               WHERE f.namespace=?1
@@ -117,7 +244,6 @@ fn resolve_target(
         )?;
         let mut rows = q.query(params![ns, name, sym])?;
         if let Some(r) = rows.next()? {
-            let file_id: i64 = r.get(0)?;
             let path: String = r.get(1)?;
             let name: String = r.get(2)?;
             let kind: String = r.get(3)?;
@@ -125,13 +251,14 @@ fn resolve_target(
             let mut end: i64 = r.get(5)?;
             let scope: Option<String> = r.get(6)?;
             let sig: Option<String>   = r.get(7)?;
+            let type_ref: Option<String> = r.get(8)?;
             if end <= 0 {
-                end = approx_end_line(conn, ns, &path, line)?;
+                end = approx_end_line(conn, root, ns, &path, line)?;
             }
             return Ok(Some(Target{
-                file_id, path, name: name.clone(),
+                path, name: name.clone(),
                 fqn: scope.map(|s| format!("{s}::{name}")),
-                kind, begin_line: line, end_line: end, signature: sig,
+                kind, begin_line: line, end_line: end, signature: sig, type_ref,
             }));
         }
     }
@@ -142,28 +269,36 @@ fn resolve_target(
             .with_context(|| format!("file not indexed: {p}"))?;
         // nearest tag starting at/above A
         let mut qt = conn.prepare(
-            "SELECT name,kind,line,COALESCE(end_line,0),scope,signature
+            "SELECT name,kind,line,COALESCE(end_line,0),scope,signature,type_ref
                FROM tags WHERE file_id=?1 AND line<=?2
-               ORDER BY line DESC LIMIT 1"
+               ORDER BY line DESC"
         )?;
+        let cands = qt.query_map(rusqlite::params![file_id, a], |r| Ok((
+            r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, i64>(2)?, r.get::<_, i64>(3)?,
+            r.get::<_, Option<String>>(4)?, r.get::<_, Option<String>>(5)?, r.get::<_, Option<String>>(6)?,
+        )))?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        // одна строка: ближайший сверху тег, чей диапазон её накрывает (функция внутри класса,
+        // иначе сам класс/namespace); не нашлось — как для A:B, ближайший сверху
+        let mut hit = None;
+        if is_single_line(rng) {
+            for c in &cands {
+                let end = if c.3 > 0 { c.3 } else { approx_end_line(conn, root, ns, p, c.2)? };
+                if end >= a { hit = Some((c.clone(), end)); break; }
+            }
+        }
+        let hit = match hit {
+            Some(h) => Some(h),
+            None => cands.into_iter().next().map(|c| { let e = c.3; (c, e) }),
+        };
 
-        let mut rows = qt.query(rusqlite::params![file_id, a])?;
-
-        if let Some(r) = rows.next()? {
-            let name: String = r.get(0)?;
-            let kind: String = r.get(1)?;
-            let line: i64    = r.get(2)?;
-            let mut end: i64 = r.get(3)?;
-            let scope: Option<String> = r.get(4)?;
-            let sig:   Option<String> = r.get(5)?;
-
+        if let Some(((name, kind, line, _, scope, sig, type_ref), mut end)) = hit {
             if end <= 0 {
                 // теперь это выполняется в функции с anyhow::Result — ? легален
-                end = b.max(approx_end_line(conn, ns, p, line)?);
+                end = b.max(approx_end_line(conn, root, ns, p, line)?);
             }
 
             return Ok(Some(Target {
-                file_id,
                 path: p.to_string(),
                 name: name.clone(),
                 fqn: scope.map(|s| format!("{s}::{}", name)),
@@ -171,11 +306,11 @@ fn resolve_target(
                 begin_line: line,
                 end_line: end,
                 signature: sig,
+                type_ref,
             }));
         } else {
             // нет тега — используем прямой диапазон
             return Ok(Some(Target {
-                file_id,
                 path: p.to_string(),
                 name: "<range>".into(),
                 fqn: None,
@@ -183,6 +318,7 @@ fn resolve_target(
                 begin_line: a,
                 end_line: b,
                 signature: None,
+                type_ref: None,
             }));
         }
 
@@ -194,14 +330,116 @@ fn split_fqn(s: &str) -> (Option<&str>, &str) {
     if let Some(pos) = s.rfind("::") { (Some(&s[..pos]), &s[pos+2..]) } else { (None, s) }
 }
 
+/// "A:B" — диапазон; "N" — одна строка (как позиция курсора в редакторе): (N, N).
+/// Строки с 1; B < A — переставляем
 fn parse_range(s: &str) -> Result<(i64,i64)> {
+    let line = |p: &str| -> Result<i64> {
+        let n: i64 = p.trim().parse().with_context(|| format!("lines must be A:B or N, got '{s}'"))?;
+        if n < 1 { bail!("lines are 1-based, got '{s}'"); }
+        Ok(n)
+    };
     let parts: Vec<_> = s.split(':').collect();
-    if parts.len()!=2 { bail!("lines must be A:B"); }
-    let a: i64 = parts[0].parse()?; let b: i64 = parts[1].parse()?;
-    Ok((a.min(b), a.max(b)))
+    match parts.as_slice() {
+        [n] => { let n = line(n)?; Ok((n, n)) }
+        [a, b] => { let (a, b) = (line(a)?, line(b)?); Ok((a.min(b), a.max(b))) }
+        _ => bail!("lines must be A:B or N, got '{s}'"),
+    }
+}
+
+fn is_single_line(s: &str) -> bool {
+    !s.contains(':')
+}
+
+/// --rev: файл цели из git-ревизии. Совпал sha с проиндексированным — теги из БД годятся,
+/// иначе прогоняем ctags по извлечённому содержимому.
+fn resolve_at_rev(
+    conn: &Connection, root: &Path, ns: &str, rev: &str,
+    symbol: Option<&str>, file: Option<&str>, lines: Option<&str>,
+) -> Result<(Target, String)> {
+    let path = match file {
+        Some(p) => p.to_string(),
+        None => resolve_target(conn, root, ns, symbol, None, None)?.map(|t| t.path)
+            .context("--rev: символ не найден в индексе — укажите --file")?,
+    };
+    let out = Command::new("git").args(["show", &format!("{rev}:{path}")]).current_dir(root).output()
+        .context("spawn git")?;
+    if !out.status.success() {
+        bail!("{path} отсутствует в ревизии {rev}: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    let text = ufs::decode_text(&out.stdout);
+
+    let indexed: Option<String> = conn.query_row(
+        "SELECT indexed_sha FROM files WHERE namespace=?1 AND path=?2", params![ns, path], |r| r.get(0))
+        .optional()?.flatten();
+    let sha = format!("{:x}", Sha256::digest(&out.stdout));
+    if indexed.as_deref() == Some(sha.as_str()) {
+        let tgt = resolve_target(conn, root, ns, symbol, Some(&path), lines)?
+            .context("не удалось определить цель (symbol/lines)")?;
+        return Ok((tgt, text));
+    }
+    eprintln!("— {rev}:{path} отличается от индекса: теги через ctags");
+    let tags = index::ctags_for_text(&path, &text)?;
+    let tgt = target_from_tags(&tags, &path, &text, symbol, lines)?
+        .context("не удалось определить цель в ревизии (symbol/lines)")?;
+    Ok((tgt, text))
 }
 
-fn approx_end_line(conn:&Connection, ns:&str, path:&str, begin:i64) -> Result<i64> {
+/// Те же правила, что у resolve_target, но по тегам в памяти
+fn target_from_tags(tags: &[index::CtagsTag], path: &str, text: &str, symbol: Option<&str>, lines: Option<&str>) -> Result<Option<Target>> {
+    let mut tags: Vec<&index::CtagsTag> = tags.iter().filter(|t| t.line.is_some()).collect();
+    tags.sort_by_key(|t| t.line);
+    let line_of = |t: &index::CtagsTag| t.line.unwrap_or(0) as i64;
+    let range = lines.map(parse_range).transpose()?;
+
+    let hit = if let Some(sym) = symbol {
+        let (_, name) = split_fqn(sym);
+        tags.iter().copied()
+            .filter(|t| t.name == name || t.scope.as_ref().is_some_and(|s| format!("{s}::{}", t.name) == sym))
+            .min_by_key(|t| (t.scope.is_none(), line_of(t)))
+    } else { None };
+    let end_of = |t: &index::CtagsTag| {
+        let begin = line_of(t);
+        let lang = t.language.as_deref().unwrap_or_default().to_ascii_lowercase();
+        match t.end_line {
+            Some(e) => e as i64,
+            None if chunk::uses_indent_blocks(&lang) => chunk::indent_end_line(text, begin),
+            None => tags.iter().map(|x| line_of(x)).find(|&l| l > begin).map(|l| l - 1)
+                .unwrap_or(text.lines().count() as i64),
+        }
+    };
+    // одна строка: накрывающий её тег, иначе (и для A:B) — ближайший сверху
+    let single = lines.is_some_and(is_single_line);
+    let hit = hit
+        .or_else(|| range.filter(|_| single).and_then(|(a, _)| tags.iter().copied().rev().find(|t| line_of(t) <= a && end_of(t) >= a)))
+        .or_else(|| range.and_then(|(a, _)| tags.iter().copied().rfind(|t| line_of(t) <= a)));
+
+    let Some(t) = hit else {
+        // нет тега — используем прямой диапазон
+        return Ok(range.map(|(a, b)| Target {
+            path: path.to_string(), name: "<range>".into(), fqn: None, kind: "block".into(),
+            begin_line: a, end_line: b, signature: None, type_ref: None,
+        }));
+    };
+    let begin = line_of(t);
+    let mut end = end_of(t);
+    if symbol.is_none() && let Some((_, b)) = range { end = end.max(b); }
+    Ok(Some(Target {
+        path: path.to_string(), name: t.name.clone(),
+        fqn: t.scope.as_ref().map(|s| format!("{s}::{}", t.name)),
+        kind: t.kind.clone(), begin_line: begin, end_line: end, signature: t.signature.clone(),
+        type_ref: t.type_ref.clone(),
+    }))
+}
+
+fn approx_end_line(conn:&Connection, root:&Path, ns:&str, path:&str, begin:i64) -> Result<i64> {
+    // Python и т.п.: конец по отступу
+    let lang: String = conn.query_row(
+        "SELECT COALESCE(lang_guess,'') FROM files WHERE namespace=?1 AND path=?2",
+        params![ns,path], |r| r.get(0)).unwrap_or_default();
+    if chunk::uses_indent_blocks(&lang) {
+        let full = ufs::read_text_sanitized(&root.join(path))?;
+        return Ok(chunk::indent_end_line(&full, begin));
+    }
     // следующий тег − 1, иначе "конец файла"
     let mut q = conn.prepare(
         "SELECT COALESCE(MIN(line),0) FROM tags t
@@ -211,16 +449,15 @@ fn approx_end_line(conn:&Connection, ns:&str, path:&str, begin:i64) -> Result<i6
     let next: i64 = q.query_row(params![ns,path,begin], |r| r.get(0))?;
     if next>0 { Ok(next-1) } else {
         // конец по числу строк в файле
-        let full = read_text_sanitized(&ufs::detect_project_root()?.join(path))?;
+        let full = ufs::read_text_sanitized(&root.join(path))?;
         Ok(full.lines().count() as i64)
     }
 }
 
 /* ---------- sections ---------- */
 
-fn section_decl_def(root:&Path, tgt:&Target, win:i64) -> Result<String> {
-    let txt = read_text_sanitized(&root.join(&tgt.path))?;
-    Ok(slice_lines(&txt, (tgt.begin_line-win).max(1), tgt.end_line+win))
+fn section_decl_def(txt:&str, tgt:&Target, win:i64) -> String {
+    ufs::slice_lines(txt, (tgt.begin_line-win).max(1), tgt.end_line+win)
 }
 
 fn section_class_type(conn:&Connection, root:&Path, ns:&str, tgt:&Target, win:i64) -> Result<String> {
@@ -238,23 +475,22 @@ fn section_class_type(conn:&Connection, root:&Path, ns:&str, tgt:&Target, win:i6
     )?;
     let row = q.query_row(params![ns,&cls], |r| Ok((r.get::<_,String>(0)?, r.get::<_,i64>(1)?, r.get::<_,i64>(2)?)));
     if let Ok((path, line, mut end)) = row {
-        if end<=0 { end = approx_end_line(conn, ns, &path, line)?; }
-        let txt = read_text_sanitized(&root.join(path))?;
-        return Ok(slice_lines(&txt, (line-win).max(1), end+win));
+        if end<=0 { end = approx_end_line(conn, root, ns, &path, line)?; }
+        let txt = ufs::read_text_sanitized(&root.join(path))?;
+        return Ok(ufs::slice_lines(&txt, (line-win).max(1), end+win));
     }
     Ok("—".into())
 }
 
-fn section_preproc(root:&Path, tgt:&Target, span:i64) -> Result<String> {
-    let txt = read_text_sanitized(&root.join(&tgt.path))?;
-    let slice = slice_lines(&txt, (tgt.begin_line-span).max(1), tgt.end_line+span);
+fn section_preproc(txt:&str, tgt:&Target, span:i64) -> String {
+    let slice = ufs::slice_lines(txt, (tgt.begin_line-span).max(1), tgt.end_line+span);
     let out = slice.lines().filter(|l| l.trim_start().starts_with('#')).take(30).collect::<Vec<_>>().join("\n");
-    Ok(if out.is_empty() {"—".into()} else {out})
+    if out.is_empty() {"—".into()} else {out}
 }
 
-fn section_callees(conn:&Connection, root:&Path, ns:&str, tgt:&Target, limit:usize) -> Result<String> {
-    let txt = read_text_sanitized(&root.join(&tgt.path))?;
-    let body = slice_lines(&txt, tgt.begin_line, tgt.end_line);
+/// Имена, похожие на вызовы, из тела цели (в порядке появления, без повторов)
+fn callee_names(txt:&str, tgt:&Target, limit:usize) -> Vec<String> {
+    let body = ufs::slice_lines(txt, tgt.begin_line, tgt.end_line);
     let re = Regex::new(r#"(?x)\b([A-Za-z_][\w:<>]*)\s*\("#).unwrap();
     let mut names = Vec::<String>::new();
     for cap in re.captures_iter(&body) {
@@ -263,12 +499,17 @@ fn section_callees(conn:&Connection, root:&Path, ns:&str, tgt:&Target, limit:usi
         if !names.iter().any(|x| x==n) { names.push(n.to_string()); }
         if names.len()>=limit { break; }
     }
+    names
+}
+
+fn section_callees(conn:&Connection, src:&str, ns:&str, tgt:&Target, limit:usize) -> Result<String> {
+    let names = callee_names(src, tgt, limit);
     if names.is_empty() { return Ok("—".into()); }
 
     // найдём сигнатуры по имени (короткому)
     let mut out = Vec::new();
     let mut qs = conn.prepare(
-        "SELECT DISTINCT t.name, t.scope, t.signature
+        "SELECT DISTINCT t.name, t.scope, t.signature, t.type_ref
            FROM tags t JOIN files f ON f.id=t.file_id
           WHERE f.namespace=?1 AND t.name=?2 AND t.kind IN ('function','prototype','member')
           LIMIT 3"
@@ -278,9 +519,10 @@ fn section_callees(conn:&Connection, root:&Path, ns:&str, tgt:&Target, limit:usi
         let mut lines = Vec::new();
         while let Some(r) = rows.next()? {
             let name:String = r.get(0)?; let scope:Option<String>=r.get(1)?;
-            let sig:Option<String> = r.get(2)?;
+            let sig:Option<String> = r.get(2)?; let type_ref:Option<String> = r.get(3)?;
             let fqn = scope.map(|s| format!("{s}::{name}")).unwrap_or(name);
-            lines.push(format!("• {}{}", fqn, sig.as_deref().unwrap_or("")));
+            let ty = type_ref.as_deref().and_then(type_name).map(|t| format!(" -> {t}")).unwrap_or_default();
+            lines.push(format!("• {}{}{ty}", fqn, sig.as_deref().unwrap_or("")));
         }
         if !lines.is_empty() {
             out.push(lines.join(" | "));
@@ -289,8 +531,97 @@ fn section_callees(conn:&Connection, root:&Path, ns:&str, tgt:&Target, limit:usi
     Ok(out.join("\n"))
 }
 
-fn section_usage_examples(conn:&Connection, ns:&str, symbol:&str, limit:usize) -> Result<String> {
+/// [EXTERNAL DEPS]: вызовы и qualified-имена (std::mutex) из тела цели, которых нет среди тегов
+/// проекта, — скорее всего stdlib/сторонние библиотеки. Локальные переменные (`cb = [&]…; cb(1)`)
+/// и параметры отсекаем: они тоже «не резолвятся», но внешними не являются
+fn section_external_deps(conn:&Connection, src:&str, ns:&str, tgt:&Target, limit:usize) -> Result<String> {
+    let body = ufs::slice_lines(src, tgt.begin_line, tgt.end_line);
+    let mut names = callee_names(src, tgt, limit * 2);
+    let qualified = Regex::new(r"\b[A-Za-z_]\w*(?:::[A-Za-z_]\w*)+").unwrap();
+    for m in qualified.find_iter(&body) {
+        if !names.iter().any(|n| n == m.as_str()) { names.push(m.as_str().to_string()); }
+    }
+    let sig = tgt.signature.as_deref().unwrap_or_default();
+    let mut known = conn.prepare(
+        "SELECT 1 FROM tags t JOIN files f ON f.id=t.file_id WHERE f.namespace=?1 AND t.name=?2 LIMIT 1")?;
+    let targs = Regex::new(r"<[^<>]*>").unwrap();
+    // локальные имена тела и параметры — один проход, а не regex на каждого кандидата
+    let assigned: HashSet<&str> = Regex::new(r"\b([A-Za-z_]\w*)\s*=[^=]").unwrap()
+        .captures_iter(&body).map(|c| c.get(1).unwrap().as_str()).collect();
+    // `Type name(args)` — объявление переменной, а не вызов (но не `return name(args)`)
+    let declared: HashSet<&str> = Regex::new(r"([\w>*&]+)\s+([A-Za-z_]\w*)\s*\(").unwrap()
+        .captures_iter(&body)
+        .filter(|c| !["return", "else", "case", "throw", "co_return", "new", "delete"].contains(&&c[1]))
+        .map(|c| c.get(2).unwrap().as_str()).collect();
+    let in_sig: HashSet<&str> = Regex::new(r"[A-Za-z_]\w*").unwrap().find_iter(sig).map(|m| m.as_str()).collect();
+    let mut out = Vec::new();
+    for n in names {
+        // std::vector<int>::push_back → push_back; короткое имя ищем в тегах
+        let plain = targs.replace_all(&n, "").to_string();
+        let short = plain.rsplit("::").next().unwrap_or(&plain);
+        if short.is_empty() || known.exists(params![ns, short])? { continue; }
+        if assigned.contains(short) || declared.contains(short) || in_sig.contains(short) { continue; }
+        // qualified-имя проектного scope (net::helper) — внутреннее, даже если тег не найден
+        if plain.contains("::") && known.exists(params![ns, plain.split("::").next().unwrap_or_default()])? { continue; }
+        if !out.contains(&plain) { out.push(plain); }
+        if out.len() >= limit { break; }
+    }
+    Ok(if out.is_empty() { "—".into() } else { out.iter().map(|n| format!("• {n}")).collect::<Vec<_>>().join("\n") })
+}
+
+/// [FILES]: заголовок цели (тот же stem) и файлы, где определено больше всего её callee.
+/// Файлы целиком, пока влезают в бюджет; файл самой цели не дублируем.
+fn section_files(conn:&Connection, root:&Path, ns:&str, tgt:&Target, src:&str, max_files:usize, budget_tokens:usize) -> Result<String> {
+    let mut picked: Vec<(String, String)> = Vec::new(); // (path, почему)
+
+    let stem = tgt.path.rsplit_once('.').map(|(s, _)| s).unwrap_or(&tgt.path);
+    let mut qh = conn.prepare("SELECT path FROM files WHERE namespace=?1 AND path=?2")?;
+    for ext in ["h","hpp","hh","hxx"] {
+        let cand = format!("{stem}.{ext}");
+        if cand != tgt.path && qh.exists(params![ns, cand])? {
+            picked.push((cand, "header".into()));
+            break;
+        }
+    }
+
+    // файл → какие callee в нём определены; чаще встречающиеся — релевантнее
+    let mut qd = conn.prepare(
+        "SELECT DISTINCT f.path FROM tags t JOIN files f ON f.id=t.file_id
+          WHERE f.namespace=?1 AND t.name=?2 AND t.kind IN ('function','member')"
+    )?;
+    let mut by_file: Vec<(String, Vec<String>)> = Vec::new();
+    for n in callee_names(src, tgt, 12) {
+        let short = n.rsplit("::").next().unwrap_or(&n).to_string();
+        let paths = qd.query_map(params![ns, short], |r| r.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for p in paths {
+            if p == tgt.path || picked.iter().any(|(x, _)| *x == p) { continue; }
+            match by_file.iter_mut().find(|(x, _)| *x == p) {
+                Some((_, v)) => v.push(short.clone()),
+                None => by_file.push((p, vec![short.clone()])),
+            }
+        }
+    }
+    by_file.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+    picked.extend(by_file.into_iter().take(max_files).map(|(p, v)| (p, format!("callees: {}", v.join(", ")))));
+
+    let mut left = budget_tokens * 4; // бюджет в символах
+    let mut out = Vec::new();
+    for (path, why) in picked {
+        let Ok(txt) = ufs::read_text_sanitized(&root.join(&path)) else { continue };
+        if txt.len() > left {
+            out.push(format!("--- {path} ({why}) — пропущен: не влез в бюджет ---"));
+            continue;
+        }
+        left -= txt.len();
+        out.push(format!("--- {path} ({why}) ---\n{}", txt.trim_end()));
+    }
+    Ok(if out.is_empty() { "—".into() } else { out.join("\n\n") })
+}
+
+fn section_usage_examples(conn:&Connection, ns:&str, tgt:&Target, limit:usize) -> Result<String> {
     // ищем в тестовых чанках упоминания имени символа
+    let symbol = tgt.name.as_str();
     let like = format!("%{}%", symbol);
     let mut q = conn.prepare(
         "SELECT f.path, c.begin_line
@@ -307,13 +638,66 @@ fn section_usage_examples(conn:&Connection, ns:&str, symbol:&str, limit:usize) -
         let path:String=r.get(0)?; let line:i64=r.get(1)?;
         out.push(format!("• {}:{}", path, line));
     }
+
+    // добор по FTS во всех чанках (examples/, samples/, docs — не только тесты), кроме самой цели
+    if out.len() < limit {
+        let mut q = conn.prepare(
+            "SELECT f.path, c.begin_line
+               FROM fts_chunks JOIN chunks c ON c.id=fts_chunks.rowid
+               JOIN files f ON f.id=c.file_id
+              WHERE fts_chunks MATCH ?1 AND f.namespace=?2
+                AND NOT (f.path=?3 AND c.begin_line<=?5 AND c.end_line>=?4)
+              ORDER BY bm25(fts_chunks)
+              LIMIT ?6"
+        )?;
+        let phrase = format!("\"{}\"", symbol.replace('"', "\"\""));
+        let mut rows = q.query(params![phrase, ns, tgt.path, tgt.begin_line, tgt.end_line, (limit * 2) as i64])?;
+        while let Some(r) = rows.next()? && out.len() < limit {
+            let path:String=r.get(0)?; let line:i64=r.get(1)?;
+            let item = format!("• {}:{}", path, line);
+            if !out.contains(&item) { out.push(item); }
+        }
+    }
+    Ok(if out.is_empty() { "—".into() } else { out.join("\n") })
+}
+
+fn section_instantiations(conn:&Connection, ns:&str, tgt:&Target, limit:usize) -> Result<String> {
+    // места, где шаблон используется с конкретными типами: name<...>; иначе — обычные вызовы name(...)
+    let name = regex::escape(&tgt.name);
+    let explicit = Regex::new(&format!(r"\b{name}\s*<[^;{{}}()]+>")).unwrap();
+    let call = Regex::new(&format!(r"\b{name}\s*\(")).unwrap();
+    let mut q = conn.prepare(
+        "SELECT f.path, c.begin_line, c.end_line, c.text
+           FROM chunks c JOIN files f ON f.id=c.file_id
+          WHERE f.namespace=?1 AND c.text LIKE ?2
+            AND COALESCE(c.symbol,'') != ?3 AND COALESCE(c.symbol,'') NOT LIKE ?4
+          ORDER BY f.path, c.begin_line"
+    )?;
+    // чанки-определения того же символа (перегрузки, копии) — не места использования
+    let mut rows = q.query(params![ns, format!("%{}%", tgt.name), tgt.name, format!("%::{}", tgt.name)])?;
+    let (mut exp_sites, mut call_sites) = (Vec::new(), Vec::new());
+    while let Some(r) = rows.next()? {
+        let path:String = r.get(0)?; let begin:i64 = r.get(1)?; let end:i64 = r.get(2)?;
+        let text:String = r.get(3)?;
+        if path == tgt.path && begin <= tgt.end_line && end >= tgt.begin_line { continue; }
+        let lines: Vec<&str> = text.lines().collect();
+        for (i, l) in lines.iter().enumerate() {
+            let site = || {
+                let ctx = lines[i.saturating_sub(1)..(i+2).min(lines.len())].join("\n");
+                format!("• {}:{}\n{}", path, begin + i as i64, ctx)
+            };
+            if explicit.is_match(l) { exp_sites.push(site()); }
+            else if call.is_match(l) { call_sites.push(site()); }
+        }
+    }
+    // явные инстанцирования информативнее вызовов с выводом типов
+    let out: Vec<String> = exp_sites.into_iter().chain(call_sites).take(limit).collect();
     Ok(if out.is_empty() { "—".into() } else { out.join("\n") })
 }
 
-fn section_comments(root:&Path, tgt:&Target, up:i64) -> Result<String> {
-    let txt = read_text_sanitized(&root.join(&tgt.path))?;
+fn section_comments(txt:&str, tgt:&Target, up:i64) -> String {
     let start = (tgt.begin_line - up).max(1);
-    let head = slice_lines(&txt, start, tgt.begin_line);
+    let head = ufs::slice_lines(txt, start, tgt.begin_line);
     // возьмём только комментарии
     let mut out = Vec::new();
     for l in head.lines().rev().take(40) {
@@ -325,20 +709,21 @@ fn section_comments(root:&Path, tgt:&Target, up:i64) -> Result<String> {
         }
     }
     out.reverse();
-    Ok(if out.is_empty() { "—".into() } else { out.join("\n") })
+    if out.is_empty() { "—".into() } else { out.join("\n") }
 }
 
 /* ---------- OpenAI call + logging ---------- */
 
-async fn call_openai(model:String, max_output:u32, facts:&str, system:&str)
--> Result<(String, Option<Usage>, String, String)> {
+async fn call_openai(root:&Path, model:String, max_output:u32, facts:&str, system:&str, gen_opts:&GenOpts, call:&CallOpts)
+-> Result<Option<(Response, PathBuf, PathBuf)>> {
+    let model = llm::resolve_model(&model);
     // messages → Input
 
     let system_msg = InputItem::Message(
         InputMessageArgs::default()
             .kind(InputMessageType::Message)                // можно опустить: Default
             .role(Role::System)
-            .content(InputContent::TextInput(system.to_string())) // <-- оборачиваем текст
+            .content(InputContent::TextInput(gen_opts.system_prompt(system))) // <-- оборачиваем текст
             .build()?
     );
 
@@ -356,75 +741,77 @@ async fn call_openai(model:String, max_output:u32, facts:&str, system:&str)
     let input :Vec<InputItem> = vec![ system_msg, user_msg ];
 
 
-    let args = CreateResponseArgs::default()
-        .model(model.clone())
-        .max_output_tokens(max_output as u32)
-        .input(Input::Items(input))
-        .build()?;
+    let mut args = CreateResponseArgs::default();
+    args.model(model.clone())
+        .max_output_tokens(llm::clamp_max_output(&model, max_output))
+        .input(Input::Items(input));
+    gen_opts.apply(&mut args);
+    let args = args.build()?;
 
 
-    // лог в /tmp
-    let ts = OffsetDateTime::now_utc().unix_timestamp();
-    let req_path  = format!("/tmp/gptcli-explain-req-{}-{}.json", model, ts);
-    let resp_path = format!("/tmp/gptcli-explain-resp-{}-{}.json", model, ts);
+    // лог запроса/ответа (GPTCLI_LOG_DIR или .gptcli/logs)
+    let (req_path, resp_path) = llm::request_log_paths(root, "explain", &model)?;
     fs::write(&req_path, serde_json::to_vec_pretty(&args)?)?;
+    if call.dry_run {
+        llm::print_dry_run(&args, &req_path, call);
+        return Ok(None);
+    }
 
-    let client = Client::new();
-    let resp = client.responses().create(args).await?;
+    llm::check_budget(root, &args, call)?;
+    let (resp, cached) = match llm::create_response(root, args, call).await {
+        Ok(r) => r,
+        Err(e) => {
+            llm::report_failed_request(&req_path, &gen_opts.system_prompt(system), facts);
+            return Err(e.into());
+        }
+    };
     fs::write(&resp_path, serde_json::to_vec_pretty(&resp)?)?;
+    llm::warn_if_incomplete(&resp);
 
-    let text = extract_output_text(&resp);
-    // usage может отсутствовать — учитываем это
-    let (pt, ct, tt) = if let Some(ref u) = resp.usage {
-        (u.input_tokens, u.output_tokens, u.total_tokens)
-    } else { (0,0,0) };
-
-    println!("{text}\n");
-    eprintln!("— usage: prompt={pt}, completion={ct}, total={tt}");
-    eprintln!("— raw request: {req_path}");
-    eprintln!("— raw response: {resp_path}");
-    Ok((text, resp.usage.clone(), req_path, resp_path))
+    llm::report_usage(root, "explain", &model, &resp, cached);
+    Ok(Some((resp, req_path, resp_path)))
 }
 
-/* ---------- text utils (sanitizer + slicing) ---------- */
-
-fn read_text_sanitized(path: &Path) -> Result<String> {
-    let bytes = fs::read(path)
-        .with_context(|| format!("read {}", path.display()))?;
-    if let Ok(s) = std::str::from_utf8(&bytes) {
-        return Ok(s.to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_single_and_span() {
+        assert_eq!(parse_range("12").unwrap(), (12, 12));
+        assert_eq!(parse_range("10:20").unwrap(), (10, 20));
+        assert_eq!(parse_range("20:10").unwrap(), (10, 20));
+        assert_eq!(parse_range(" 3 : 4 ").unwrap(), (3, 4));
+        assert!(is_single_line("12") && !is_single_line("12:12"));
     }
-    Ok(sanitize_non_utf8_runs(&bytes))
-}
 
-fn sanitize_non_utf8_runs(bytes: &[u8]) -> String {
-    let mut out = String::with_capacity(bytes.len());
-    let mut in_non_ascii = false;
-    for &b in bytes {
-        match b {
-            b'\n' | b'\t' | b'\r' => {
-                if in_non_ascii { out.push_str("???"); in_non_ascii = false; }
-                out.push(b as char);
-            }
-            0x20..=0x7E => {
-                if in_non_ascii { out.push_str("???"); in_non_ascii = false; }
-                out.push(b as char);
-            }
-            _ => { in_non_ascii = true; }
+    #[test]
+    fn parse_range_rejects_malformed() {
+        for bad in ["", "a", "1:", ":2", "1:2:3", "3:x", "0", "0:5", "-1"] {
+            assert!(parse_range(bad).is_err(), "{bad}");
         }
     }
-    if in_non_ascii { out.push_str("???"); }
-    out
-}
 
-fn slice_lines(full:&str, begin:i64, end:i64) -> String {
-    let mut res = String::new();
-    for (idx, line) in full.lines().enumerate() {
-        let ln = (idx as i64)+1;
-        if ln < begin { continue; }
-        if ln > end { break; }
-        res.push_str(line);
-        res.push('\n');
+    #[test]
+    fn external_deps_skip_locals_params_and_project_names() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE files(id INTEGER PRIMARY KEY, namespace TEXT, path TEXT);
+             CREATE TABLE tags(file_id INTEGER, name TEXT);
+             INSERT INTO files VALUES(1,'p','a.cpp');
+             INSERT INTO tags VALUES(1,'helper');").unwrap();
+        let src = "void run(Callback done) {\n\
+                   \x20 auto cb = [&](int x) { return x; };\n\
+                   \x20 std::string s(\"x\");\n\
+                   \x20 cb(1); helper(2); done(3);\n\
+                   \x20 printf(\"%d\", std::max(1, 2));\n\
+                   \x20 return compute(4);\n\
+                   }\n";
+        let tgt = Target {
+            path: "a.cpp".into(), name: "run".into(), fqn: None, kind: "function".into(),
+            begin_line: 1, end_line: 7, signature: Some("(Callback done)".into()), type_ref: None,
+        };
+        let deps = section_external_deps(&conn, src, "p", &tgt, 24).unwrap();
+        assert_eq!(deps, "• printf\n• std::max\n• compute\n• std::string");
     }
-    res
 }
@@ -0,0 +1,65 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection};
+use std::{fs::File, io::{BufWriter, Write}, path::PathBuf};
+
+use crate::{commands::sql, db::open_db_readonly, fs as ufs, state::ProjectState};
+
+/// Таблицы в порядке выгрузки: files раньше tags/chunks — import сопоставляет file_id по ходу чтения
+const TABLES: [(&str, &str); 3] = [
+    ("files", "SELECT f.* FROM files f WHERE f.namespace=?1 ORDER BY f.id"),
+    ("tags", "SELECT t.* FROM tags t JOIN files f ON f.id=t.file_id WHERE f.namespace=?1 ORDER BY t.id"),
+    ("chunks", "SELECT c.* FROM chunks c JOIN files f ON f.id=c.file_id WHERE f.namespace=?1 ORDER BY c.id"),
+];
+
+/// Выгрузка активного namespace: по объекту на строку таблицы, с полем "table".
+/// ndjson — объект на строку (для jq и внешних RAG-хранилищ), json — один массив.
+/// Строки пишутся по мере чтения из БД, в памяти индекс целиком не держим.
+pub fn run(out: PathBuf, format: String, what: Vec<String>) -> Result<()> {
+    let ndjson = match format.as_str() {
+        "ndjson" => true,
+        "json" => false,
+        other => bail!("unknown format '{other}': ndjson or json"),
+    };
+    if let Some(w) = what.iter().find(|w| !TABLES.iter().any(|(t, _)| t == w)) {
+        bail!("unknown --what '{w}': files, tags, chunks");
+    }
+    let root = ufs::detect_project_root()?;
+    let ns = ProjectState::load(&root)?.active_namespace();
+    let conn = open_db_readonly(&root)?;
+
+    let mut w = BufWriter::new(File::create(&out).with_context(|| format!("create {}", out.display()))?);
+    if !ndjson { w.write_all(b"[")?; }
+    let mut first = true;
+    let mut counts = Vec::new();
+    for (table, query) in TABLES {
+        if !what.is_empty() && !what.iter().any(|x| x == table) { continue; }
+        let n = export_table(&conn, &ns, table, query, &mut w, ndjson, &mut first)?;
+        counts.push(format!("{n} {table}"));
+    }
+    if !ndjson { w.write_all(if first { b"]\n" } else { b"\n]\n" })?; }
+    w.flush()?;
+    eprintln!("export {ns}: {} → {}", counts.join(", "), out.display());
+    Ok(())
+}
+
+fn export_table(
+    conn: &Connection, ns: &str, table: &str, query: &str, w: &mut impl Write, ndjson: bool, first: &mut bool,
+) -> Result<usize> {
+    let mut stmt = conn.prepare(query)?;
+    let cols: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+    let mut rows = stmt.query(params![ns])?;
+    let mut n = 0;
+    while let Some(r) = rows.next()? {
+        let mut obj = serde_json::Map::with_capacity(cols.len() + 1);
+        obj.insert("table".into(), table.into());
+        for (i, c) in cols.iter().enumerate() {
+            obj.insert(c.clone(), sql::to_json(r.get_ref(i)?));
+        }
+        if !ndjson { w.write_all(if *first { b"\n" } else { b",\n" })?; }
+        serde_json::to_writer(&mut *w, &obj)?;
+        if ndjson { w.write_all(b"\n")?; }
+        *first = false;
+        n += 1;
+    }
+    Ok(n)
+}
@@ -0,0 +1,119 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::{params_from_iter, types::Value, Connection, Transaction};
+use std::{collections::HashMap, fs::File, io::{BufRead, BufReader}, path::PathBuf};
+
+use crate::{db::open_db, fs as ufs, plan::Plan};
+
+/// Загрузить NDJSON из export под namespace `ns`: files — upsert по (namespace, path) со сбросом
+/// старых тегов и чанков файла, tags/chunks — с file_id, переписанным на новые id.
+/// fts_chunks обновляют триггеры chunks. Колонки, которых нет в этой версии схемы, пропускаются.
+pub fn run(file: PathBuf, ns: String, dry_run: bool) -> Result<()> {
+    let root = ufs::detect_project_root()?;
+    let mut conn = open_db(&root)?;
+    let reader = BufReader::new(File::open(&file).with_context(|| format!("open {}", file.display()))?);
+
+    let tx = conn.transaction()?;
+    let columns: HashMap<&str, Vec<String>> = ["files", "tags", "chunks"].into_iter()
+        .map(|t| Ok((t, table_columns(&tx, t)?)))
+        .collect::<Result<_>>()?;
+    let mut file_ids: HashMap<i64, i64> = HashMap::new();
+    let (mut files, mut tags, mut chunks, mut orphans) = (0usize, 0usize, 0usize, 0usize);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() { continue; }
+        if i == 0 && line.trim_start().starts_with('[') {
+            bail!("{}: JSON array, import reads NDJSON (export --format ndjson)", file.display());
+        }
+        let mut row: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: not a JSON object", file.display(), i + 1))?;
+        let table = row.remove("table").and_then(|t| t.as_str().map(str::to_string))
+            .with_context(|| format!("{}:{}: no \"table\" field", file.display(), i + 1))?;
+        let old_id = row.remove("id").and_then(|v| v.as_i64());
+        match table.as_str() {
+            "files" => {
+                let old_id = old_id.with_context(|| format!("{}:{}: file without id", file.display(), i + 1))?;
+                row.insert("namespace".into(), ns.clone().into());
+                let id = upsert_file(&tx, &columns["files"], &row)?;
+                file_ids.insert(old_id, id);
+                files += 1;
+            }
+            "tags" | "chunks" => {
+                let Some(&file_id) = row.get("file_id").and_then(|v| v.as_i64()).and_then(|f| file_ids.get(&f)) else {
+                    orphans += 1;
+                    continue;
+                };
+                row.insert("file_id".into(), file_id.into());
+                insert_row(&tx, &table, &columns[table.as_str()], &row)?;
+                if table == "tags" { tags += 1 } else { chunks += 1 }
+            }
+            other => bail!("{}:{}: unknown table '{other}'", file.display(), i + 1),
+        }
+    }
+    if dry_run {
+        tx.rollback()?;
+        let mut plan = Plan::new("import");
+        plan.add(&format!("files upserted into {ns}"), files, &[]);
+        plan.add("tags inserted", tags, &[]);
+        plan.add("chunks inserted", chunks, &[]);
+        plan.add("rows skipped (file not in export)", orphans, &[]);
+        plan.print();
+        return Ok(());
+    }
+    tx.commit()?;
+    if orphans > 0 {
+        eprintln!("warn: {orphans} tags/chunks skipped: their file is not in the export");
+    }
+    println!("import → {ns}: {files} files, {tags} tags, {chunks} chunks");
+    Ok(())
+}
+
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut q = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let cols = q.query_map([], |r| r.get::<_, String>(1))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(cols)
+}
+
+/// Поля строки, для которых есть колонка (кроме id), и их значения
+fn known_fields(columns: &[String], row: &serde_json::Map<String, serde_json::Value>) -> (Vec<String>, Vec<Value>) {
+    row.iter()
+        .filter(|(k, _)| *k != "id" && columns.contains(k))
+        .map(|(k, v)| (k.clone(), to_sql(v)))
+        .unzip()
+}
+
+fn to_sql(v: &serde_json::Value) -> Value {
+    match v {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => n.as_i64().map(Value::Integer).unwrap_or_else(|| Value::Real(n.as_f64().unwrap_or_default())),
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        other => Value::Text(other.to_string()),
+    }
+}
+
+fn placeholders(n: usize) -> String {
+    (1..=n).map(|i| format!("?{i}")).collect::<Vec<_>>().join(",")
+}
+
+/// Повторный import того же файла заменяет его теги и чанки, а не дублирует их
+fn upsert_file(tx: &Transaction, columns: &[String], row: &serde_json::Map<String, serde_json::Value>) -> Result<i64> {
+    let (cols, vals) = known_fields(columns, row);
+    if !cols.iter().any(|c| c == "path") { bail!("file row without path"); }
+    let updates: Vec<String> = cols.iter().filter(|c| *c != "namespace" && *c != "path")
+        .map(|c| format!("{c}=excluded.{c}")).collect();
+    let sql = format!(
+        "INSERT INTO files({}) VALUES({}) ON CONFLICT(namespace, path) DO UPDATE SET {} RETURNING id",
+        cols.join(","), placeholders(cols.len()),
+        if updates.is_empty() { "path=excluded.path".to_string() } else { updates.join(",") });
+    let id: i64 = tx.prepare_cached(&sql)?.query_row(params_from_iter(vals), |r| r.get(0))?;
+    tx.execute("DELETE FROM tags WHERE file_id=?1", [id])?;
+    tx.execute("DELETE FROM chunks WHERE file_id=?1", [id])?;
+    Ok(id)
+}
+
+fn insert_row(tx: &Transaction, table: &str, columns: &[String], row: &serde_json::Map<String, serde_json::Value>) -> Result<()> {
+    let (cols, vals) = known_fields(columns, row);
+    let sql = format!("INSERT INTO {table}({}) VALUES({})", cols.join(","), placeholders(cols.len()));
+    tx.prepare_cached(&sql)?.execute(params_from_iter(vals))?;
+    Ok(())
+}
@@ -1,37 +1,40 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use clap::Args;
 use rusqlite::{params, Connection};
 use serde::Deserialize;
 use std::{
     collections::HashMap,
-    fs,
     io::Write,
-    path::{Path, PathBuf},
+    path::Path,
     process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 use time::{OffsetDateTime};
 
-use crate::{db::open_db, fs as ufs, state::ProjectState};
+use crate::{commands::chunk::{self, TagSpan}, db::open_db, fs as ufs, plan::Plan, state::ProjectState};
 
 #[derive(Debug, Deserialize, Clone)]
-struct CtagsTag {
-    name: String,
-    path: String,
+pub(crate) struct CtagsTag {
+    pub(crate) name: String,
+    pub(crate) path: String,
     #[serde(default)]
-    kind: String,                    // "function" | "class" | ... (с +K long kind)
+    pub(crate) kind: String,                    // "function" | "class" | ... (с +K long kind)
     #[serde(default)]
-    language: Option<String>,        // "C" | "C++"
+    pub(crate) language: Option<String>,        // "C" | "C++" | "Rust" | "Python" | ...
     #[serde(default)]
-    line: Option<u32>,
+    pub(crate) line: Option<u32>,
     #[serde(default, rename="end")]
-    end_line: Option<u32>,           // если сборка ctags умеет
+    pub(crate) end_line: Option<u32>,           // если сборка ctags умеет
     #[serde(default)]
-    scope: Option<String>,           // ns::Class
+    pub(crate) scope: Option<String>,           // ns::Class
     #[serde(default, rename="scopeKind")]
-    scope_kind: Option<String>,
+    pub(crate) scope_kind: Option<String>,
     #[serde(default)]
-    signature: Option<String>,
+    pub(crate) signature: Option<String>,
     #[serde(default, rename="typeref")]
-    type_ref: Option<String>,
+    pub(crate) type_ref: Option<String>,        // "typename:int" — возвращаемый тип / тип поля
+    #[serde(default)]
+    pub(crate) access: Option<String>,          // public|protected|private (члены классов)
 }
 
 #[derive(Debug)]
@@ -40,30 +43,72 @@ struct PendingFile {
     rel_path: String,
     sha: String,
     mtime: i64,
+    lang: String,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct IndexArgs {
+    /// Обработать только первые N ожидающих файлов (по пути)
+    #[arg(long)] pub limit_files: Option<usize>,
+    /// После успешной индексации выполнить GPTCLI_POST_INDEX_HOOK (список файлов — в stdin);
+    /// переменная не задана или хук завершился с ошибкой — index завершается ошибкой
+    #[arg(long)] pub run_hooks: bool,
+    #[command(flatten)] pub chunking: chunk::Strategy,
+    /// Показать, что будет переиндексировано и сколько тегов/чанков запишется, ничего не записывая
+    #[arg(long)] pub dry_run: bool,
+    /// После индексации досчитать эмбеддинги чанков (для search --semantic); модель — MODEL,
+    /// GPTCLI_EMBED_MODEL или text-embedding-3-small
+    #[arg(long, value_name = "MODEL", num_args = 0..=1, default_missing_value = "")] pub embed: Option<String>,
+    /// Запускать ctags в N потоков пачками файлов; сбой пачки повторяется по файлу, так что
+    /// плохой файл индексируется без тегов, а не валит весь index. 1 — один процесс на всё
+    #[arg(long, value_name = "N", default_value_t = 1)] pub ctags_jobs: usize,
+    /// Переписывать только чанки с изменившимся sha (reindex-changed)
+    #[arg(skip)] pub incremental_chunks: bool,
+    /// Без построчного прогресса в stdout (bench: stdout — только отчёт)
+    #[arg(skip)] pub quiet: bool,
 }
 
-pub fn run() -> Result<()> {
+pub fn run(args: IndexArgs) -> Result<()> {
+    let IndexArgs { limit_files, run_hooks, chunking, dry_run, incremental_chunks, embed: _, ctags_jobs, quiet } = args;
+    chunking.validate()?;
+    // хук проверяем до индексации: без него --run-hooks не выполнить, а ctags уже отработал бы
+    let hook = if run_hooks {
+        match std::env::var("GPTCLI_POST_INDEX_HOOK") {
+            Ok(h) if !h.trim().is_empty() => Some(h),
+            _ => bail!("--run-hooks: GPTCLI_POST_INDEX_HOOK is not set"),
+        }
+    } else {
+        None
+    };
     let root = ufs::detect_project_root()?;
     let st = ProjectState::load(&root)?;
     let mut conn = open_db(&root)?;
 
-    let pending = pending_files(&conn, &st.namespace)?;
+    let mut pending = pending_files(&conn, &st.active_namespace())?;
     if pending.is_empty() {
-        println!("index: up-to-date (нет изменённых файлов)");
+        if !quiet { println!("index: up-to-date (нет изменённых файлов)"); }
         return Ok(());
     }
+    // --limit-files: быстрые итерации над настройками; порядок детерминирован (ORDER BY path)
+    if let Some(n) = limit_files && n < pending.len() {
+        if !quiet { println!("index: partial run, {n} of {} pending files", pending.len()); }
+        pending.truncate(n);
+    }
 
     // Список путей для ctags (относительно корня)
     let paths: Vec<String> = pending.iter().map(|p| p.rel_path.clone()).collect();
-    let tags = run_ctags(&root, &paths).context("ctags failed")?;
+    let tags = if ctags_jobs > 1 {
+        run_ctags_parallel(&root, &paths, ctags_jobs)
+    } else {
+        run_ctags(&root, &paths).context("ctags failed")?
+    };
 
     // Группируем теги по пути
     let mut by_path: HashMap<String, Vec<CtagsTag>> = HashMap::new();
     for t in tags {
         if t.line.is_none() { continue; }
-        let k = t.kind.as_str();
-        // фильтруем только полезные для чанкинга
-        if !matches!(k, "function" | "class" | "struct" | "namespace" | "prototype" | "member" | "enum" | "union" | "typedef") {
+        // фильтруем только полезные для чанкинга (по таблице видов языка)
+        if chunk::canonical_kind(t.language.as_deref().unwrap_or(""), &t.kind).is_none() {
             continue;
         }
         by_path.entry(t.path.clone()).or_default().push(t);
@@ -77,38 +122,51 @@ pub fn run() -> Result<()> {
 
     // Транзакция на весь батч
     let tx = conn.transaction()?;
-    {
+    let (unreadable, indexed, tags_written, chunks_written, chunks_removed) = {
         let mut del_tags   = tx.prepare("DELETE FROM tags WHERE file_id=?1")?;
         let mut del_chunks = tx.prepare("DELETE FROM chunks WHERE file_id=?1")?;
         let mut ins_tag = tx.prepare(
-            "INSERT INTO tags(file_id,name,kind,line,scope,scope_kind,signature,lang,end_line)
-             VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9)")?;
-        let mut ins_chunk = tx.prepare(
-            "INSERT INTO chunks(file_id,kind,symbol,begin_line,end_line,sha,mtime,text)
-             VALUES(?1,?2,?3,?4,?5,?6,?7,?8)")?;
+            "INSERT INTO tags(file_id,name,kind,line,scope,scope_kind,signature,lang,end_line,type_ref,access)
+             VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11)")?;
         let mut upd_file = tx.prepare(
-            "UPDATE files SET indexed_sha=?1, indexed_at=?2 WHERE id=?3")?;
+            "UPDATE files SET indexed_sha=?1, indexed_at=?2, index_error=NULL WHERE id=?3")?;
+        // нечитаемый файл: ошибка записывается, indexed_sha сбрасывается — ошибка бывает временной
+        // (права, блокировка, удалён между scan и index), так что следующий index пробует снова
+        let mut fail_file = tx.prepare(
+            "UPDATE files SET indexed_sha=NULL, indexed_at=?1, index_error=?2 WHERE id=?3")?;
 
         let total = pending.len();
+        let mut unreadable = 0usize;
+        let mut indexed = Vec::<String>::new();
+        let (mut chunks_written, mut chunks_removed, mut chunks_kept) = (0usize, 0usize, 0usize);
+        let mut tags_written = 0usize;
 
         for (idx, pf) in pending.into_iter().enumerate() {
-            println!("Indexing {}/{} : {}", idx+1, total, &pf.rel_path);
+            if !dry_run && !quiet {
+                println!("Indexing {}/{} : {}", idx+1, total, &pf.rel_path);
+            }
             // читаем текст файла (для чанков)
             let abs = root.join(&pf.rel_path);
-            let file_text = match read_text_sanitized(&abs) {
+            let file_text = match ufs::read_text_sanitized(&abs) {
                 Ok(s) => s,
                 Err(e) => {
                     eprintln!("warn: не удалось прочитать {}: {e}", abs.display());
+                    // старые теги/чанки больше не соответствуют диску
+                    del_tags.execute(params![pf.id])?;
+                    del_chunks.execute(params![pf.id])?;
+                    fail_file.execute(params![now, e.to_string(), pf.id])?;
+                    unreadable += 1;
                     continue;
                 }
             };
-            let total_lines = (file_text.lines().count() as i64).max(1);
 
             // теги по файлу
             let ftags = by_path.get(pf.rel_path.as_str()).map(|v| v.as_slice()).unwrap_or(&[]);
             // пересоздаём индексацию
             del_tags.execute(params![pf.id])?;
-            del_chunks.execute(params![pf.id])?;
+            if !incremental_chunks {
+                del_chunks.execute(params![pf.id])?;
+            }
 
             // вставляем теги
             for t in ftags {
@@ -122,45 +180,90 @@ pub fn run() -> Result<()> {
                     t.signature,
                     t.language.as_deref().unwrap_or(""),
                     t.end_line.map(|x| x as i64),
+                    t.type_ref,
+                    t.access,
                 ])?;
             }
+            tags_written += ftags.len();
 
-            // строим чанки v1
-            let chunk_specs = build_chunks_v1(ftags, total_lines);
-            for c in chunk_specs {
-                let text = slice_text(&file_text, c.begin_line, c.end_line);
-                let sha = sha256_str(&text);
-                let symbol = c.symbol;
-                ins_chunk.execute(params![
-                    pf.id,
-                    c.kind,
-                    symbol,
-                    c.begin_line,
-                    c.end_line,
-                    sha,
-                    pf.mtime,
-                    text,
-                ])?;
-            }
+            // чанки: стратегия из флагов; при полной индексации старые уже удалены выше
+            let spans: Vec<TagSpan> = ftags.iter().map(TagSpan::from).collect();
+            let (written, removed, kept) = chunk::rechunk_file(&tx, pf.id, pf.mtime, &spans, &file_text, &pf.lang, &chunking)?;
+            chunks_written += written;
+            chunks_removed += removed;
+            chunks_kept += kept;
 
             // отметить файл как проиндексированный
             upd_file.execute(params![pf.sha, now, pf.id])?;
+            indexed.push(pf.rel_path);
         }
-    } // statements drop here
+        if incremental_chunks && !dry_run && !quiet {
+            println!("chunks: {chunks_written} written, {chunks_removed} removed, {chunks_kept} unchanged");
+        }
+        (unreadable, indexed, tags_written, chunks_written, chunks_removed)
+    }; // statements drop here
 
+    if dry_run {
+        // ctags уже отработал, записи сделаны в транзакции — откатываем
+        tx.rollback()?;
+        let mut plan = Plan::new(if incremental_chunks { "reindex-changed" } else { "index" });
+        plan.add("files to index", indexed.len(), &indexed);
+        plan.add("unreadable files", unreadable, &[]);
+        plan.add("tags written", tags_written, &[]);
+        plan.add("chunks written", chunks_written, &[]);
+        if incremental_chunks { plan.add("chunks removed", chunks_removed, &[]); }
+        if let Some(hook) = &hook { plan.add("post-index hook would run (GPTCLI_POST_INDEX_HOOK)", 1, std::slice::from_ref(hook)); }
+        plan.print();
+        return Ok(());
+    }
     tx.commit()?;
-    println!("index: ok");
+    if !quiet {
+        match unreadable {
+            0 => println!("index: ok"),
+            n => println!("index: ok ({n} unreadable files, see files.index_error)"),
+        }
+    }
+    if let Some(hook) = &hook {
+        run_post_index_hook(&root, hook, &st.active_namespace(), &indexed)?;
+    }
     Ok(())
 }
 
 // -------- helpers --------
 
+/// Внешний шаг после индексации (эмбеддинги, уведомления): команда из GPTCLI_POST_INDEX_HOOK
+/// через `sh -c` в корне проекта; изменённые файлы — построчно в stdin. Ненулевой код выхода —
+/// ошибка команды (индекс к этому моменту уже записан)
+fn run_post_index_hook(root: &Path, hook: &str, ns: &str, changed: &[String]) -> Result<()> {
+    let mut child = Command::new("sh")
+        .args(["-c", hook])
+        .current_dir(root)
+        .env("GPTCLI_NAMESPACE", ns)
+        .env("GPTCLI_CHANGED_COUNT", changed.len().to_string())
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("spawn hook: {hook}"))?;
+    {
+        let mut stdin = child.stdin.take().expect("stdin");
+        for p in changed {
+            // хук может не читать stdin — это не ошибка
+            if writeln!(stdin, "{p}").is_err() { break; }
+        }
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("post-index hook failed ({status}); the index itself is saved: {hook}");
+    }
+    eprintln!("— post-index hook: ok");
+    Ok(())
+}
+
 fn pending_files(conn: &Connection, ns: &str) -> Result<Vec<PendingFile>> {
     let mut q = conn.prepare(
-        "SELECT id, path, COALESCE(sha,''), COALESCE(mtime,0)
+        "SELECT id, path, COALESCE(sha,''), COALESCE(mtime,0), COALESCE(lang_guess,'')
            FROM files
           WHERE namespace=?1
-            AND (indexed_sha IS NULL OR indexed_sha != sha)
+            AND (indexed_sha IS NULL OR indexed_sha != sha OR index_error IS NOT NULL)
           ORDER BY path"
     )?;
     let mut rows = q.query(params![ns])?;
@@ -171,12 +274,17 @@ fn pending_files(conn: &Connection, ns: &str) -> Result<Vec<PendingFile>> {
             rel_path: r.get(1)?,
             sha: r.get(2)?,
             mtime: r.get(3)?,
+            lang: r.get(4)?,
         });
     }
     Ok(out)
 }
 
 fn run_ctags(project_root: &Path, paths: &[String]) -> Result<Vec<CtagsTag>> {
+    // все языки, что знает ctags; лишние виды отсечёт chunk::canonical_kind.
+    // GPTCLI_CTAGS_LANGUAGES сужает набор (синтаксис ctags: "C,C++,Rust")
+    let languages = std::env::var("GPTCLI_CTAGS_LANGUAGES").unwrap_or_else(|_| "all".into());
+    let languages = format!("--languages={languages}");
     // запускаем из корня проекта, чтобы относительные пути совпадали с теми, что в БД
     let mut child = Command::new("ctags");
     child.current_dir(project_root);
@@ -184,8 +292,8 @@ fn run_ctags(project_root: &Path, paths: &[String]) -> Result<Vec<CtagsTag>> {
         .args([
             "-n",
             "--output-format=json",
-            "--languages=C,C++",
-            "--fields=+KlnSmt",
+            languages.as_str(),
+            "--fields=+KlnSmta",
             "--extras=+F",
             "--sort=no",
             "-L",
@@ -220,103 +328,57 @@ fn run_ctags(project_root: &Path, paths: &[String]) -> Result<Vec<CtagsTag>> {
     Ok(tags)
 }
 
-// Простая версия чанкинга: один тег → один чанк
-#[derive(Debug)]
-struct ChunkSpec {
-    kind: String,
-    symbol: Option<String>,
-    begin_line: i64, // 1-based
-    end_line: i64,   // inclusive
-}
-
-fn build_chunks_v1(tags: &[CtagsTag], total_lines: i64) -> Vec<ChunkSpec> {
-    let mut out = Vec::new();
-    if tags.is_empty() {
-        // нет тегов — пока пропускаем (можно добавить fallback блоки позже)
-        return out;
-    }
-    for (i, t) in tags.iter().enumerate() {
-        let begin = t.line.unwrap_or(1) as i64;
-        let end = if let Some(e) = t.end_line { e as i64 }
-                  else if let Some(next) = tags.get(i + 1) {
-                      (next.line.unwrap_or((begin + 1) as u32) as i64 - 1).max(begin)
-                  } else {
-                      total_lines
-                  };
-        let sym = if let Some(scope) = &t.scope {
-            Some(format!("{scope}::{}", t.name))
-        } else {
-            Some(t.name.clone())
-        };
-        let kind = match t.kind.as_str() {
-            "function" | "prototype" | "member" => "function",
-            "class" | "struct" => "class",
-            "namespace" => "namespace",
-            "enum" => "enum",
-            "union" => "union",
-            "typedef" => "typedef",
-            _ => "block",
-        }.to_string();
-
-        // защита от мусора
-        if begin <= 0 || end < begin { continue; }
-
-        out.push(ChunkSpec {
-            kind,
-            symbol: sym,
-            begin_line: begin,
-            end_line: end,
-        });
-    }
-    out
-}
-
-fn sanitize_non_utf8_runs(bytes: &[u8]) -> String {
-    let mut out = String::with_capacity(bytes.len());
-    let mut in_non_ascii = false;
-    for &b in bytes {
-        match b {
-            b'\n' | b'\t' | b'\r' => {
-                if in_non_ascii { out.push_str("???"); in_non_ascii = false; }
-                out.push(b as char);
-            }
-            0x20..=0x7E => { // печатный ASCII
-                if in_non_ascii { out.push_str("???"); in_non_ascii = false; }
-                out.push(b as char);
+/// ctags пулом из `jobs` потоков: потоки разбирают пачки путей из общей очереди.
+/// Пачка нужна, чтобы не платить запуском процесса за каждый файл; при сбое пачку
+/// прогоняем по одному файлу, и теряются теги только у того файла, на котором ctags падает
+fn run_ctags_parallel(project_root: &Path, paths: &[String], jobs: usize) -> Vec<CtagsTag> {
+    // ~4 пачки на поток для ровной загрузки, но не больше 256 файлов в пачке
+    let batch = paths.len().div_ceil(jobs * 4).clamp(1, 256);
+    let batches: Vec<&[String]> = paths.chunks(batch).collect();
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        let workers: Vec<_> = (0..jobs.min(batches.len())).map(|_| s.spawn(|| {
+            let mut out = Vec::new();
+            while let Some(batch) = batches.get(next.fetch_add(1, Ordering::Relaxed)) {
+                match run_ctags(project_root, batch) {
+                    Ok(tags) => out.extend(tags),
+                    Err(_) => for p in batch.iter() {
+                        match run_ctags(project_root, std::slice::from_ref(p)) {
+                            Ok(tags) => out.extend(tags),
+                            Err(e) => eprintln!("warning: ctags failed on {p}: {e:#}; indexing it without tags"),
+                        }
+                    },
+                }
             }
-            _ => { in_non_ascii = true; }
-        }
-    }
-    if in_non_ascii { out.push_str("???"); }
-    out
+            out
+        })).collect();
+        workers.into_iter().flat_map(|w| w.join().expect("ctags worker panicked")).collect()
+    })
 }
 
-fn read_text_sanitized(path: &std::path::Path) -> anyhow::Result<String> {
-    let bytes = std::fs::read(path)?;
-    // Если это валидный UTF-8 — не трогаем
-    if let Ok(s) = std::str::from_utf8(&bytes) {
-        return Ok(s.to_string());
+impl From<&CtagsTag> for TagSpan {
+    fn from(t: &CtagsTag) -> Self {
+        TagSpan {
+            name: t.name.clone(),
+            kind: t.kind.clone(),
+            line: t.line.unwrap_or(0) as i64,
+            end_line: t.end_line.map(|e| e as i64),
+            scope: t.scope.clone(),
+            language: t.language.clone().unwrap_or_default(),
+        }
     }
-    Ok(sanitize_non_utf8_runs(&bytes))
 }
 
-fn slice_text(full: &str, begin_line: i64, end_line: i64) -> String {
-    // берём [begin-1, end) построчно; сохраняем разделители строк как '\n'
-    let mut res = String::new();
-    for (idx, line) in full.lines().enumerate() {
-        let ln = (idx as i64) + 1;
-        if ln < begin_line { continue; }
-        if ln > end_line { break; }
-        res.push_str(line);
-        res.push('\n');
-    }
-    res
+/// Теги для содержимого не с диска (explain --rev): пишем во временный каталог под тем же
+/// именем файла, чтобы ctags определил язык по расширению
+pub(crate) fn ctags_for_text(rel: &str, text: &str) -> Result<Vec<CtagsTag>> {
+    let name = Path::new(rel).file_name().context("empty path")?.to_string_lossy().to_string();
+    let dir = std::env::temp_dir().join(format!("gptcli-ctags-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(&name), text)?;
+    let tags = run_ctags(&dir, &[name]);
+    let _ = std::fs::remove_dir_all(&dir);
+    tags
 }
 
-fn sha256_str(s: &str) -> String {
-    use sha2::{Digest, Sha256};
-    let mut h = Sha256::new();
-    h.update(s.as_bytes());
-    format!("{:x}", h.finalize())
-}
 
@@ -1,22 +1,56 @@
-use anyhow::{Context, Result};
-use crate::{fs as ufs, state::ProjectState};
-use crate::db::open_db;
+use anyhow::{bail, Context, Result};
+use crate::{fs as ufs, plan::Plan, state::ProjectState};
+use crate::db::{open_db, SCHEMA_VERSION};
 
-pub fn run(namespace_opt: Option<String>) -> Result<()> {
+pub fn run(namespace_opt: Option<String>, subprojects: Vec<String>, web_base: Option<String>, force: bool, dry_run: bool) -> Result<()> {
     let root = ufs::detect_project_root()?;
-    ufs::ensure_project_dirs(&root)?;
+    // повторный init затёр бы namespace, подпроекты и last_head — только явно
+    let initialized = root.join(".gptcli/state.json").exists();
+    if !force && initialized {
+        let existing = ProjectState::load(&root).map(|st| st.namespace).unwrap_or_else(|_| "?".into());
+        bail!("уже инициализировано (namespace {existing}); --force перезапишет state.json");
+    }
+    if !dry_run {
+        ufs::ensure_project_dirs(&root)?;
+    }
 
-    // по умолчанию namespace = basename(root)@main (можно улучшить позже)
-    let default_ns = format!("{}@main", root.file_name().unwrap().to_string_lossy());
+    // по умолчанию namespace = basename(root)@<текущая ветка>; не git или detached HEAD — @main
+    let branch = ufs::git_output(&root, &["rev-parse", "--abbrev-ref", "HEAD"])
+        .filter(|b| b != "HEAD")
+        .unwrap_or_else(|| "main".into());
+    let default_ns = format!("{}@{branch}", root.file_name().unwrap().to_string_lossy());
     let namespace = namespace_opt.unwrap_or(default_ns);
 
-    let st = ProjectState::new(root.clone(), namespace)?;
+    let subprojects = subprojects.into_iter().map(|s| s.trim_matches('/').to_string()).collect();
+    let mut st = ProjectState::new(root.clone(), namespace, subprojects)?;
+    // происхождение репозитория: origin и его HEAD (origin/main → main)
+    st.remote_url = ufs::git_output(&root, &["remote", "get-url", "origin"]);
+    st.default_branch = ufs::git_output(&root, &["symbolic-ref", "--short", "refs/remotes/origin/HEAD"])
+        .map(|s| s.trim_start_matches("origin/").to_string());
+    st.web_base = web_base;
+    if dry_run {
+        let mut plan = Plan::new("init");
+        plan.add(if initialized { "state.json overwritten" } else { "state.json created" }, 1, &[st.namespace.clone()]);
+        if initialized {
+            // --force сбрасывает то, что накопилось в state.json
+            let old = ProjectState::load(&root).ok();
+            let lost: Vec<String> = old.iter()
+                .flat_map(|o| [o.last_head.as_ref().map(|h| format!("last_head {h}")), o.current_thread_id.as_ref().map(|t| format!("current thread {t}"))])
+                .flatten().collect();
+            plan.add("state fields reset", lost.len(), &lost);
+        }
+        plan.add("index.sqlite created", (!crate::db::db_path(&root).exists()) as usize, &[]);
+        plan.print();
+        return Ok(());
+    }
     st.save().context("failed to save state")?;
 
     let _conn = open_db(&root)?;
-
     println!("Инициализировано: {}", root.display());
-    println!("• .gptcli/state.json\n• .gptcli/index.sqlite (schema v1)");
+    println!("• .gptcli/state.json\n• .gptcli/index.sqlite (schema v{SCHEMA_VERSION})");
+    if let Some(url) = &st.remote_url {
+        println!("Remote: {url} ({})", st.default_branch.as_deref().unwrap_or("-"));
+    }
 
     Ok(())
 }
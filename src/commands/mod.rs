@@ -7,13 +7,15 @@ pub mod stats;
 pub mod summarize;
 pub mod budget;
 pub mod explain;
+pub mod ask;
+pub mod prompt;
+pub mod sql;
+pub mod export;
+pub mod import;
+pub mod search;
+pub mod rerank;
+pub mod symbols;
+pub mod thread;
+pub mod bench;
 
-pub use explain::*;
-pub use init::*;
-pub use scan::*;
-pub use chunk::*;
-pub use index::*;
-pub use reindex_changed::*;
-pub use stats::*;
 pub use summarize::*;
-pub use budget::*;
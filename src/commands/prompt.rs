@@ -0,0 +1,115 @@
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}};
+
+use crate::{fs as ufs, plan::Plan};
+
+/// Именованные шаблоны запросов для `ask --prompt`; плейсхолдеры {question} и {code}
+#[derive(Subcommand, Debug)]
+pub enum PromptCmd {
+    /// Сохранить (или перезаписать) шаблон
+    Save {
+        name: String,
+        template: String,
+        /// Показать, что изменится, ничего не записывая
+        #[arg(long)] dry_run: bool,
+    },
+    /// Список шаблонов
+    List,
+    /// Показать шаблон
+    Show { name: String },
+    /// Удалить шаблон
+    Rm {
+        name: String,
+        /// Показать, что изменится, ничего не записывая
+        #[arg(long)] dry_run: bool,
+    },
+}
+
+/// Библиотека лежит в .gptcli/prompts.json — файл можно закоммитить и делить с командой
+fn library_path(root: &Path) -> PathBuf {
+    root.join(".gptcli/prompts.json")
+}
+
+fn load_library(root: &Path) -> Result<BTreeMap<String, String>> {
+    let p = library_path(root);
+    if !p.exists() { return Ok(BTreeMap::new()); }
+    let s = fs::read_to_string(&p).with_context(|| format!("read {}", p.display()))?;
+    serde_json::from_str(&s).with_context(|| format!("parse {}", p.display()))
+}
+
+fn save_library(root: &Path, lib: &BTreeMap<String, String>) -> Result<()> {
+    let p = library_path(root);
+    ufs::write_atomic(&p, (serde_json::to_string_pretty(lib)? + "\n").as_bytes())
+}
+
+impl PromptCmd {
+    pub fn dry_run(&self) -> bool {
+        matches!(self, PromptCmd::Save { dry_run: true, .. } | PromptCmd::Rm { dry_run: true, .. })
+    }
+}
+
+pub fn run(cmd: PromptCmd) -> Result<()> {
+    let root = ufs::detect_project_root()?;
+    let mut lib = load_library(&root)?;
+    match cmd {
+        PromptCmd::Save { name, template, dry_run } => {
+            let replaced = lib.insert(name.clone(), template).is_some();
+            if dry_run {
+                let mut plan = Plan::new("prompt save");
+                plan.add(if replaced { "prompts updated" } else { "prompts added" }, 1, &[name]);
+                plan.add("files written", 1, &[library_path(&root).display().to_string()]);
+                plan.print();
+                return Ok(());
+            }
+            save_library(&root, &lib)?;
+            println!("prompt '{name}' {}", if replaced { "updated" } else { "saved" });
+        }
+        PromptCmd::List => {
+            if lib.is_empty() {
+                println!("No prompts yet (prompt save NAME TEMPLATE)");
+            }
+            for (name, template) in &lib {
+                let first = template.lines().next().unwrap_or_default();
+                println!("{name:<16} {first}");
+            }
+        }
+        PromptCmd::Show { name } => println!("{}", get(&lib, &name)?),
+        PromptCmd::Rm { name, dry_run } => {
+            if lib.remove(&name).is_none() { bail!("unknown prompt '{name}'"); }
+            if dry_run {
+                let mut plan = Plan::new("prompt rm");
+                plan.add("prompts removed", 1, &[name]);
+                plan.add("files written", 1, &[library_path(&root).display().to_string()]);
+                plan.print();
+                return Ok(());
+            }
+            save_library(&root, &lib)?;
+            println!("prompt '{name}' removed");
+        }
+    }
+    Ok(())
+}
+
+fn get<'a>(lib: &'a BTreeMap<String, String>, name: &str) -> Result<&'a String> {
+    lib.get(name).with_context(|| {
+        let known: Vec<&str> = lib.keys().map(String::as_str).collect();
+        format!("unknown prompt '{name}', known: [{}]", known.join(", "))
+    })
+}
+
+/// Шаблон `name`, заполненный вопросом и кодом. Плейсхолдер без значения — ошибка,
+/// а не запрос с буквальным `{code}`; вопрос без {question} в шаблоне дописывается в конец
+pub(crate) fn render(root: &Path, name: &str, question: Option<&str>, code: Option<&str>) -> Result<String> {
+    let lib = load_library(root)?;
+    let mut text = get(&lib, name)?.clone();
+    for (key, value, flag) in [("{question}", question, "a question"), ("{code}", code, "--file")] {
+        if !text.contains(key) { continue; }
+        let Some(value) = value else { bail!("prompt '{name}' uses {key}: pass {flag}") };
+        text = text.replace(key, value);
+    }
+    if let Some(q) = question && !lib[name].contains("{question}") {
+        text = format!("{text}\n\n{q}");
+    }
+    Ok(text)
+}
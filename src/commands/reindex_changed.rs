@@ -1,6 +1,168 @@
-
 use anyhow::Result;
-pub fn run() -> Result<()> {
-    println!("reindex-changed: TODO — обновлять только изменённые файлы/чанки");
+use rusqlite::{params, Connection};
+use std::{collections::BTreeSet, path::Path, time::UNIX_EPOCH};
+
+use super::index::{self, IndexArgs};
+use super::scan;
+use crate::{db::open_db, fs as ufs, plan::Plan, state::ProjectState};
+
+/// Переиндексация без полного scan: изменённые файлы берём из git (diff от last_head + untracked)
+/// и сверкой mtime/size известных файлов с диском — stat дешёвый, а ловит то, чего git уже не видит
+/// (откат правки, удалённый untracked). Строки files обновляются на месте, дальше обычный index
+/// с пообъектным обновлением чанков.
+pub fn run(args: IndexArgs) -> Result<()> {
+    args.chunking.validate()?;
+    let root = ufs::detect_project_root()?;
+    let mut st = ProjectState::load(&root)?;
+    let ns = st.active_namespace();
+    let mut conn = open_db(&root)?;
+
+    let head = ufs::git_output(&root, &["rev-parse", "--short", "HEAD"]);
+    let mut paths: BTreeSet<String> = mtime_changed(&conn, &root, &ns)?.into_iter().collect();
+    let source = match git_changed(&root, st.last_head.as_deref()) {
+        Some(git) => {
+            paths.extend(git);
+            format!("git diff {} + mtime", st.last_head.as_deref().unwrap_or("HEAD"))
+        }
+        None => "mtime".to_string(),
+    };
+    // в подпроекте — только его поддерево
+    let prefix = st.subproject.as_ref().map(|d| format!("{d}/"));
+    let paths: Vec<String> = paths.into_iter().filter(|p| prefix.as_ref().is_none_or(|d| p.starts_with(d))).collect();
+
+    let types = scan::source_types()?;
+    let ignore = scan::load_gptcliignore(&root);
+    let (mut updated, mut removed) = (Vec::new(), Vec::new());
+    for rel in &paths {
+        let abs = root.join(rel);
+        if !abs.is_file() {
+            removed.push(rel.clone());
+            continue;
+        }
+        // тот же фильтр, что у scan: типы файлов, каталоги сборки и .gptcliignore
+        let skipped = Path::new(rel).components().any(|c| scan::is_skipped_dir(&c.as_os_str().to_string_lossy()));
+        if skipped || !types.matched(rel, false).is_whitelist() { continue; }
+        if ignore.as_ref().is_some_and(|gi| gi.matched_path_or_any_parents(rel, false).is_ignore()) {
+            // уже проиндексированный файл, попавший под .gptcliignore, убираем из индекса
+            removed.push(rel.clone());
+            continue;
+        }
+        updated.push(rel.clone());
+    }
+
+    if args.dry_run {
+        let mut plan = Plan::new("reindex-changed (refresh)");
+        plan.add(&format!("files changed ({source})"), updated.len(), &updated);
+        plan.add("files removed", removed.len(), &removed);
+        plan.print();
+        // index ниже покажет то, что уже ожидает индексации
+        return index::run(IndexArgs { incremental_chunks: true, ..args });
+    }
+
+    let refreshed = refresh_files(&mut conn, &root, &ns, &updated, &removed)?;
+    println!("reindex-changed: {refreshed} changed, {} removed (via {source})", removed.len());
+    drop(conn);
+
+    index::run(IndexArgs { incremental_chunks: true, ..args })?;
+    // следующий запуск сравнивает с этим коммитом
+    if head.is_some() && head != st.last_head {
+        st.last_head = head;
+        st.save()?;
+    }
     Ok(())
 }
+
+/// Пути (от `root`), изменённые с коммита `since`: рабочее дерево и индекс против since,
+/// плюс неотслеживаемые. Без since (первый запуск) — против HEAD: `diff HEAD`, `diff --cached`
+/// (в репозитории без коммитов HEAD нет) и untracked из `status --porcelain`.
+/// None — не git или since недоступен (gc, чужая ветка).
+fn git_changed(root: &Path, since: Option<&str>) -> Option<Vec<String>> {
+    // git отдаёт пути от toplevel, а root может быть его подкаталогом (--root, GPTCLI_ROOT)
+    let top = std::fs::canonicalize(ufs::git_output(root, &["rev-parse", "--show-toplevel"])?).ok()?;
+    let root = std::fs::canonicalize(root).ok()?;
+    // core.quotePath=false: не-ASCII имена как есть, а не восьмеричными escape
+    let git = |args: &[&str]| ufs::git_lines(root.as_path(), &[&["-c", "core.quotePath=false"], args].concat());
+    let mut set = BTreeSet::new();
+    let mut diff = |rev: &str| git(&["diff", "--name-only", "--no-renames", rev]).map(|paths| set.extend(paths));
+    match since {
+        Some(since) => diff(since)?,
+        None => {
+            let _ = diff("HEAD");
+            diff("--cached")?;
+        }
+    }
+    // "?? path" — неотслеживаемый; пути porcelain — тоже от toplevel
+    let status = git(&["status", "--porcelain", "--untracked-files=all"]).unwrap_or_default();
+    set.extend(status.iter().filter_map(|e| e.strip_prefix("?? ")).map(str::to_string));
+    Some(set.into_iter()
+        .filter_map(|p| top.join(&p).strip_prefix(&root).ok().map(|r| r.to_string_lossy().to_string()))
+        .collect())
+}
+
+/// Файлы namespace, у которых на диске другие mtime/size (или которых больше нет).
+/// Новые файлы так не найти — их даёт git (untracked), а вне git нужен scan.
+fn mtime_changed(conn: &Connection, root: &Path, ns: &str) -> Result<Vec<String>> {
+    let mut q = conn.prepare("SELECT path, COALESCE(size,0), COALESCE(mtime,0) FROM files WHERE namespace=?1 ORDER BY path")?;
+    let rows = q.query_map(params![ns], |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?, r.get::<_, i64>(2)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows.into_iter().filter(|(rel, size, mtime)| {
+        match file_meta(&root.join(rel)) {
+            Some((s, m)) => s != *size || m != *mtime,
+            None => true,
+        }
+    }).map(|(rel, _, _)| rel).collect())
+}
+
+fn file_meta(p: &Path) -> Option<(i64, i64)> {
+    let md = std::fs::metadata(p).ok()?;
+    let mtime = md.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    Some((md.len() as i64, mtime))
+}
+
+/// Обновить строки files как это сделал бы scan, но только для заданных путей, одной транзакцией.
+/// Нечитаемый файл, как в scan, — предупреждение и пропуск: его строка остаётся прежней.
+/// Дубликат из file_aliases (scan --dedup-content) снова становится файлом, а после записи
+/// дубликаты сворачиваются заново — правка копии не отменяет dedup, а изменившаяся копия
+/// перестаёт быть алиасом. Возвращает, сколько из `updated` обновлено
+fn refresh_files(conn: &mut Connection, root: &Path, ns: &str, updated: &[String], removed: &[String]) -> Result<usize> {
+    let now = std::time::SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let tx = conn.transaction()?;
+    let mut refreshed = 0;
+    let dedup: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM file_aliases WHERE namespace=?1)", params![ns], |r| r.get(0))?;
+    {
+        let mut unalias = tx.prepare("DELETE FROM file_aliases WHERE namespace=?1 AND path=?2")?;
+        let mut upsert = tx.prepare(
+            r#"INSERT INTO files(namespace,path,size,mtime,sha,lang_guess,doc_kind,seen_at)
+            VALUES(?,?,?,?,?,?,?,?)
+            ON CONFLICT(namespace,path) DO UPDATE SET
+            size=excluded.size, mtime=excluded.mtime, sha=excluded.sha,
+            lang_guess=excluded.lang_guess, doc_kind=excluded.doc_kind, seen_at=excluded.seen_at"#,
+        )?;
+        let mut del = tx.prepare("DELETE FROM files WHERE namespace=?1 AND path=?2")?;
+        for rel in updated {
+            let abs = root.join(rel);
+            let (size, mtime) = file_meta(&abs).unwrap_or((0, 0));
+            let sha = match scan::sha256_file(&abs) {
+                Ok(sha) => sha,
+                Err(e) => {
+                    eprintln!("warning: skip {rel}: {e:#}");
+                    continue;
+                }
+            };
+            unalias.execute(params![ns, rel])?;
+            upsert.execute(params![ns, rel, size, mtime, sha, scan::guess_lang(rel), scan::classify_doc(rel), now])?;
+            refreshed += 1;
+        }
+        for rel in removed {
+            unalias.execute(params![ns, rel])?;
+            del.execute(params![ns, rel])?;
+        }
+    }
+    if dedup {
+        let collapsed = scan::collapse_duplicates(&tx, ns)?;
+        if collapsed > 0 { eprintln!("— dedup: {collapsed} duplicates collapsed"); }
+    }
+    tx.commit()?;
+    Ok(refreshed)
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use async_openai::types::responses::{CreateResponseArgs, Input, InputContent, InputItem, InputMessageArgs, InputMessageType, Role};
+use clap::Args;
+use rusqlite::{params, Connection};
+use std::{collections::HashMap, path::Path};
+use time::OffsetDateTime;
+
+use crate::{commands::{extract_output_text, search::Hit}, llm::{self, CallOpts}};
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct RerankOpts {
+    /// Второй этап поиска: --rerank-pool лучших кандидатов оценивает модель, остаются k лучших по её оценке
+    #[arg(long)] pub rerank: bool,
+    /// Модель для оценки релевантности (лучше дешёвая); по умолчанию gpt-4.1-mini
+    #[arg(long)] pub rerank_model: Option<String>,
+    /// Сколько кандидатов FTS/semantic отдать на оценку
+    #[arg(long, default_value_t = 30)] pub rerank_pool: usize,
+}
+
+impl RerankOpts {
+    /// Сколько кандидатов достать из поиска, чтобы в выдаче осталось k
+    pub(crate) fn pool(&self, k: usize) -> usize {
+        if self.rerank { self.rerank_pool.max(k) } else { k }
+    }
+}
+
+const SYSTEM: &str = "Ты оцениваешь, насколько фрагмент кода помогает ответить на запрос. \
+Для каждого фрагмента — оценка от 0 (не относится) до 10 (отвечает на запрос). \
+Ответ — только строки вида `номер: оценка`, по одной на фрагмент, без пояснений.";
+
+/// Фрагмент в запросе на оценку: первые строки, чтобы 30 кандидатов не стоили как весь ответ
+const EXCERPT_LINES: usize = 40;
+
+/// Переранжирование: оценки LLM (0–10) вместо bm25/косинуса, k лучших; при равных — порядок поиска.
+/// Оценки кэшируются в rerank_cache по (запрос, sha чанка, модель) — повторный запрос модель не зовёт.
+/// --dry-run: модель не вызываем, без кэшированной оценки кандидат остаётся на месте поиска;
+/// --over-budget из `call` действует и на вызов оценки
+pub(crate) async fn rerank(root: &Path, conn: &Connection, query: &str, hits: Vec<Hit>, k: usize, opts: &RerankOpts, call: &CallOpts)
+    -> Result<Vec<Hit>> {
+    let dry_run = call.dry_run;
+    let model = llm::resolve_model(opts.rerank_model.as_deref().unwrap_or("gpt-4.1-mini"));
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut scores: Vec<Option<f64>> = hits.iter()
+        .map(|h| cached_score(conn, &query, &h.sha, &model))
+        .collect::<Result<_>>()?;
+    let missing: Vec<usize> = (0..hits.len()).filter(|&i| scores[i].is_none()).collect();
+    if !missing.is_empty() && dry_run {
+        eprintln!("— dry run: {} of {} candidates not reranked ({model} not called)", missing.len(), hits.len());
+    } else if !missing.is_empty() {
+        let fresh = score_with_llm(root, &query, &missing.iter().map(|&i| &hits[i]).collect::<Vec<_>>(), &model, call.over_budget).await?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        for (n, &i) in missing.iter().enumerate() {
+            let Some(&s) = fresh.get(&(n + 1)) else { continue };
+            scores[i] = Some(s);
+            conn.execute(
+                "INSERT OR REPLACE INTO rerank_cache(query,sha,model,score,created_at) VALUES(?1,?2,?3,?4,?5)",
+                params![query, hits[i].sha, model, s, now])?;
+        }
+        let unscored = missing.len() - fresh.len().min(missing.len());
+        if unscored > 0 {
+            eprintln!("⚠ rerank: {model} gave no score for {unscored} candidates; they rank last");
+        }
+    }
+    // dry-run с неоценёнными — порядок поиска
+    Ok(top_k(scores, hits, k, !dry_run || missing.is_empty()))
+}
+
+/// k лучших по оценке (стабильно: при равных — порядок поиска, без оценки — в конец);
+/// sort = false — порядок поиска как есть. score у оценённых — оценка модели
+fn top_k(scores: Vec<Option<f64>>, hits: Vec<Hit>, k: usize, sort: bool) -> Vec<Hit> {
+    let mut ranked: Vec<(Option<f64>, Hit)> = scores.into_iter().zip(hits).collect();
+    if sort {
+        ranked.sort_by(|a, b| b.0.unwrap_or(-1.0).total_cmp(&a.0.unwrap_or(-1.0)));
+    }
+    ranked.into_iter().take(k).map(|(s, mut h)| {
+        if let Some(s) = s { h.score = s; }
+        h
+    }).collect()
+}
+
+fn cached_score(conn: &Connection, query: &str, sha: &str, model: &str) -> Result<Option<f64>> {
+    let mut q = conn.prepare_cached("SELECT score FROM rerank_cache WHERE query=?1 AND sha=?2 AND model=?3")?;
+    let mut rows = q.query(params![query, sha, model])?;
+    Ok(match rows.next()? {
+        Some(r) => Some(r.get(0)?),
+        None => None,
+    })
+}
+
+/// Один запрос на всех кандидатов; ответ — номер кандидата (с 1) → оценка
+async fn score_with_llm(root: &Path, query: &str, hits: &[&Hit], model: &str, over_budget: bool) -> Result<HashMap<usize, f64>> {
+    let mut user = format!("[QUERY]\n{query}\n\n[CANDIDATES]\n");
+    for (n, h) in hits.iter().enumerate() {
+        let sym = h.symbol_label().map(|s| format!(" {} {s}", h.kind)).unwrap_or_default();
+        let excerpt = h.text.lines().take(EXCERPT_LINES).collect::<Vec<_>>().join("\n");
+        user.push_str(&format!("### {} {}:{}-{}{sym}\n{excerpt}\n\n", n + 1, h.path, h.begin_line, h.end_line));
+    }
+    let args = CreateResponseArgs::default()
+        .model(model)
+        .max_output_tokens(llm::clamp_max_output(model, 16 + 8 * hits.len() as u32))
+        .input(Input::Items(vec![
+            InputItem::Message(InputMessageArgs::default()
+                .kind(InputMessageType::Message)
+                .role(Role::System)
+                .content(InputContent::TextInput(SYSTEM.to_string()))
+                .build()?),
+            InputItem::Message(InputMessageArgs::default()
+                .role(Role::User)
+                .content(InputContent::TextInput(user.clone()))
+                .build()?),
+        ]))
+        .build()?;
+    let (req_path, resp_path) = llm::request_log_paths(root, "rerank", model)?;
+    std::fs::write(&req_path, serde_json::to_vec_pretty(&args)?)?;
+    // оценки кэшируются по чанкам в rerank_cache, кэш ответов целиком здесь не нужен
+    let call = CallOpts { no_cache: true, over_budget, ..Default::default() };
+    llm::check_budget(root, &args, &call)?;
+    let (resp, _) = match llm::create_response(root, args, &call).await {
+        Ok(r) => r,
+        Err(e) => {
+            llm::report_failed_request(&req_path, SYSTEM, &user);
+            return Err(e.into());
+        }
+    };
+    std::fs::write(&resp_path, serde_json::to_vec_pretty(&resp)?)?;
+    llm::warn_if_incomplete(&resp);
+    eprintln!("— rerank {model}: {} candidates", hits.len());
+    llm::report_usage(root, "rerank", model, &resp, false);
+    Ok(parse_scores(&extract_output_text(&resp), hits.len()))
+}
+
+/// Строки `N: S` (допускаются `#N`, `N.`, `N)`, `N -`); номера вне 1..=n и мусор пропускаем,
+/// оценку приводим к 0..10
+fn parse_scores(text: &str, n: usize) -> HashMap<usize, f64> {
+    let mut out = HashMap::new();
+    for line in text.lines() {
+        let t = line.trim().trim_start_matches(['#', '-', '*', ' ']);
+        let Some(split) = t.find(|c: char| !c.is_ascii_digit()) else { continue };
+        let Ok(idx) = t[..split].parse::<usize>() else { continue };
+        let rest = t[split..].trim_start_matches([':', '.', ')', '-', '=', ' ', '\t']);
+        let num: String = rest.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+        let Ok(score) = num.trim_end_matches('.').parse::<f64>() else { continue };
+        if (1..=n).contains(&idx) {
+            out.entry(idx).or_insert(score.clamp(0.0, 10.0));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_scores_accepts_common_shapes() {
+        let s = parse_scores("1: 7\n#2 - 3.5\n3) 10\n4. 12\nnoise\n9: 5\n1: 0", 4);
+        assert_eq!(s.get(&1), Some(&7.0));
+        assert_eq!(s.get(&2), Some(&3.5));
+        assert_eq!(s.get(&3), Some(&10.0));
+        // вне шкалы — к 10, номер вне списка и повтор — мимо
+        assert_eq!(s.get(&4), Some(&10.0));
+        assert_eq!(s.len(), 4);
+    }
+
+    fn hit(path: &str, score: f64) -> Hit {
+        Hit {
+            namespace: "p@main".into(), path: path.into(), begin_line: 1, end_line: 2, kind: "function".into(),
+            symbol: None, part: None, lang: "rust".into(), sha: path.into(), text: String::new(), score,
+        }
+    }
+
+    fn paths(hits: &[Hit]) -> Vec<(&str, f64)> {
+        hits.iter().map(|h| (h.path.as_str(), h.score)).collect()
+    }
+
+    #[test]
+    fn top_k_orders_by_llm_score_stably() {
+        let hits = vec![hit("a", -3.0), hit("b", -2.0), hit("c", -1.0), hit("d", -0.5)];
+        let top = top_k(vec![Some(2.0), None, Some(9.0), Some(2.0)], hits, 3, true);
+        assert_eq!(paths(&top), vec![("c", 9.0), ("a", 2.0), ("d", 2.0)]);
+    }
+
+    #[test]
+    fn top_k_without_sort_keeps_search_order() {
+        let hits = vec![hit("a", -3.0), hit("b", -2.0), hit("c", -1.0)];
+        let top = top_k(vec![None, Some(9.0), None], hits, 2, false);
+        assert_eq!(paths(&top), vec![("a", -3.0), ("b", 9.0)]);
+    }
+}
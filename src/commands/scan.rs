@@ -1,45 +1,99 @@
 use anyhow::{Context, Result};
-use ignore::{types::TypesBuilder, WalkBuilder};
+use clap::Args;
+use ignore::{gitignore::{Gitignore, GitignoreBuilder}, types::{Types, TypesBuilder}, WalkBuilder};
 use rusqlite::params;
 use sha2::{Digest, Sha256};
 use std::{
     fs::File,
     io::{BufReader, Read},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Mutex,
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::{db::open_db, fs as ufs, state::ProjectState};
+use crate::{db::open_db, fs as ufs, plan::Plan, state::ProjectState};
 
-pub fn run() -> Result<()> {
+#[derive(Args, Debug, Default)]
+pub struct ScanArgs {
+    /// Индексировать только один файл из группы с одинаковым содержимым
+    #[arg(long)] pub dedup_content: bool,
+    /// Файл с glob-шаблонами исключений (по одному на строку), поверх .gitignore
+    #[arg(long)] pub exclude_paths_file: Option<String>,
+    /// Показать, что изменится в БД, ничего не записывая
+    #[arg(long)] pub dry_run: bool,
+    /// Пересчитать sha всех файлов, даже если size и mtime не изменились
+    #[arg(long)] pub full: bool,
+}
+
+/// `full` — пересчитать sha всех файлов; иначе файлы с теми же size и mtime, что в БД, не читаются
+pub fn run(args: ScanArgs) -> Result<()> {
+    let ScanArgs { dedup_content, exclude_paths_file, dry_run, full } = args;
     let root = ufs::detect_project_root()?;
     let st = ProjectState::load(&root)?;
     let mut conn = open_db(&root)?;
+    let ns = st.active_namespace();
+    let excludes = exclude_paths_file.as_deref().map(|p| load_excludes(&root, Path::new(p))).transpose()?;
 
-    // --- файловые типы (пока C/C++ + манифесты; расширим языковыми пакетами позже)
-    let mut tb = TypesBuilder::new();
-    for g in ["*.c","*.cc","*.cpp","*.cxx","*.h","*.hh","*.hpp","*.inl","*.ipp"] { tb.add("code", g)?; }
-    for g in ["CMakeLists.txt","*.cmake","Makefile","meson.build","conanfile.*","vcpkg.json","compile_commands.json","README*","*.md"] {
-        tb.add("meta", g)?;
-    }
-    let types = tb.select("code").select("meta").build()?;
+    let types = source_types()?;
 
     // --- исключения директорий (поверх .gitignore)
-    let mut wb = WalkBuilder::new(&root);
+    // --subproject: обходим только его поддерево, пути остаются относительно корня
+    let walk_root = st.subproject.as_ref().map(|d| root.join(d)).unwrap_or_else(|| root.clone());
+    let mut wb = WalkBuilder::new(&walk_root);
     wb.types(types).hidden(false).follow_links(false).git_ignore(true);
+    wb.add_custom_ignore_filename(IGNORE_FILE);
     wb.filter_entry(|e| {
         let Some(name) = e.file_name().to_str() else { return true };
         if name == ".git" || name == ".gptcli" { return false; }
         if e.path().is_dir() {
-            return !matches!(name,
-                "build"|"out"|"dist"|"target"|"node_modules"|"__pycache__"|".cache"|".ccls-cache"|".venv"|"venv"
-            ) && !name.starts_with("cmake-build-");
+            return !is_skipped_dir(name);
         }
         true
     });
 
     let mut files = 0usize;
     let mut bytes = 0u64;
+    let mut excluded = 0usize;
+    // что уже есть в БД (path → size, mtime, sha): повторное использование sha
+    // и, для --dry-run, отличие новых файлов от изменённых
+    let known: std::collections::HashMap<String, (i64, i64, String)> = {
+        let mut q = conn.prepare("SELECT path, COALESCE(size,0), COALESCE(mtime,0), COALESCE(sha,'') FROM files WHERE namespace=?1")?;
+        q.query_map(params![ns], |r| Ok((r.get(0)?, (r.get(1)?, r.get(2)?, r.get(3)?))))?.collect::<rusqlite::Result<_>>()?
+    };
+    let (mut added, mut changed) = (Vec::<String>::new(), Vec::<String>::new());
+
+    // 1) обход: только фильтры и stat, без чтения содержимого
+    let mut candidates = Vec::new();
+    for dent in wb.build() {
+        let Ok(entry) = dent else { continue };
+        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+            continue;
+        }
+        let path = entry.path();
+        let rel = path.strip_prefix(&root).unwrap().to_string_lossy().to_string();
+        if let Some(ex) = &excludes && ex.matched_path_or_any_parents(&rel, false).is_ignore() {
+            tracing::debug!(path = %rel, "scan: excluded");
+            excluded += 1;
+            continue;
+        }
+        let md = entry.metadata().ok();
+        let size = md.as_ref().map(|m| m.len() as i64).unwrap_or(0);
+        let mtime = md
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        // size и mtime как в прошлый раз — содержимое считаем тем же (правку с откатом mtime ловит --full)
+        let cached = known.get(&rel)
+            .filter(|(s, m, sha)| !full && *s == size && *m == mtime && !sha.is_empty())
+            .map(|(_, _, sha)| sha.clone());
+        candidates.push(Candidate { rel, path: path.to_path_buf(), size, mtime, cached });
+    }
+    let reused = candidates.iter().filter(|c| c.cached.is_some()).count();
+
+    // 2) sha256 параллельно; нечитаемый файл пропускаем с предупреждением
+    let hashed = hash_all(candidates);
+    let unreadable = hashed.iter().filter(|(_, sha)| sha.is_none()).count();
 
     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
     let tx = conn.transaction()?;
@@ -55,38 +109,180 @@ pub fn run() -> Result<()> {
         )?;
 
 
-        for dent in wb.build() {
-            let Ok(entry) = dent else { continue };
-            if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
-                continue;
-            }
-            let path = entry.path();
-            let rel = path.strip_prefix(&root).unwrap().to_string_lossy().to_string();
-            let md = entry.metadata().ok();
-            let size = md.as_ref().map(|m| m.len() as i64).unwrap_or(0);
-            let mtime = md
-                .and_then(|m| m.modified().ok())
-                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
-                .map(|d| d.as_secs() as i64)
-                .unwrap_or(0);
-
-            let sha = sha256_file(path).unwrap_or_else(|_| String::new());
+        // 3) запись одной транзакцией, в порядке обхода
+        for (Candidate { rel, size, mtime, .. }, sha) in hashed {
+            let Some(sha) = sha else { continue };
             let lang = guess_lang(&rel);
             let kind = classify_doc(&rel);
+            tracing::debug!(path = %rel, lang, kind, size, "scan: file");
 
-            upsert.execute(params![st.namespace, rel, size, mtime, sha, lang, kind, now])?;
+            if dry_run {
+                match known.get(&rel) {
+                    None => added.push(rel.clone()),
+                    Some((_, _, old)) if *old != sha => changed.push(rel.clone()),
+                    _ => {}
+                }
+            }
+            upsert.execute(params![ns, rel, size, mtime, sha, lang, kind, now])?;
             files += 1;
             bytes += size as u64;
         }
 
     }
+    // алиасы отражают только последний scan
+    tx.execute("DELETE FROM file_aliases WHERE namespace=?1", params![ns])?;
+    let collapsed = if dedup_content { collapse_duplicates(&tx, &ns)? } else { 0 };
+    if dry_run {
+        // всё посчитано внутри транзакции — откатываем её
+        tx.rollback()?;
+        let mut plan = Plan::new("scan");
+        plan.add("files new", added.len(), &added);
+        plan.add("files changed", changed.len(), &changed);
+        plan.add("files unchanged", files - added.len() - changed.len(), &[]);
+        if exclude_paths_file.is_some() { plan.add("files excluded", excluded, &[]); }
+        if dedup_content { plan.add("duplicates collapsed", collapsed, &[]); }
+        plan.print();
+        return Ok(());
+    }
     tx.commit()?;
 
-    eprintln!("— scanned: {files} files, ~{} KB", bytes / 1024);
+    eprintln!("— scanned: {files} files, ~{} KB ({} hashed, {reused} unchanged by size/mtime)",
+        bytes / 1024, files.saturating_sub(reused));
+    if unreadable > 0 {
+        eprintln!("— skipped unreadable: {unreadable} files");
+    }
+    tracing::info!(namespace = %ns, files, kb = bytes / 1024, reused, excluded, collapsed, unreadable, "scan done");
+    if let Some(p) = &exclude_paths_file {
+        eprintln!("— excluded by {p}: {excluded} files");
+    }
+    if dedup_content {
+        eprintln!("— dedup: {collapsed} duplicates collapsed");
+    }
     Ok(())
 }
 
-fn sha256_file(p: &Path) -> Result<String> {
+/// Файл, прошедший фильтры обхода; sha считается отдельно
+struct Candidate {
+    rel: String,
+    path: PathBuf,
+    size: i64,
+    mtime: i64,
+    /// sha из БД, если файл по size/mtime не менялся
+    cached: Option<String>,
+}
+
+/// sha256 кандидатов (кроме тех, у кого он уже есть из БД) на потоках, порядок сохраняется.
+/// Потоки берут файлы из общей очереди по одному — один большой файл не держит чужую порцию.
+/// Потоков — по числу ядер, вручную — RAYON_NUM_THREADS (как у инструментов на rayon).
+/// Ошибка чтения — None и предупреждение с путём: один закрытый файл не роняет весь scan.
+fn hash_all(candidates: Vec<Candidate>) -> Vec<(Candidate, Option<String>)> {
+    let jobs = std::env::var("RAYON_NUM_THREADS").ok().and_then(|v| v.parse::<usize>().ok()).filter(|&n| n > 0)
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+        .min(candidates.len().max(1));
+    let hash = |mut c: Candidate| {
+        if let Some(sha) = c.cached.take() { return (c, Some(sha)); }
+        let sha = sha256_file(&c.path)
+            .inspect_err(|e| eprintln!("warning: skip {}: {e:#}", c.rel))
+            .ok();
+        (c, sha)
+    };
+    let queue = Mutex::new(candidates.into_iter().enumerate());
+    let mut done: Vec<(usize, (Candidate, Option<String>))> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..jobs).map(|_| s.spawn(|| {
+            let mut out = Vec::new();
+            loop {
+                // замок держим только на время next(), хэшируем без него
+                let Some((i, c)) = queue.lock().expect("hash queue poisoned").next() else { break };
+                out.push((i, hash(c)));
+            }
+            out
+        })).collect();
+        workers.into_iter().flat_map(|w| w.join().expect("hash worker panicked")).collect()
+    });
+    done.sort_unstable_by_key(|(i, _)| *i);
+    done.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Файловые типы, которые попадают в индекс (пока C/C++ + манифесты; расширим языковыми пакетами позже)
+pub(crate) fn source_types() -> Result<Types> {
+    let mut tb = TypesBuilder::new();
+    for g in ["*.c","*.cc","*.cpp","*.cxx","*.h","*.hh","*.hpp","*.inl","*.ipp"] { tb.add("code", g)?; }
+    // языки, которые index разбирает через ctags помимо C/C++ (см. chunk::canonical_kind)
+    for g in ["*.rs","*.py","*.ts","*.tsx","*.js","*.jsx","*.lua"] { tb.add("code", g)?; }
+    for g in ["CMakeLists.txt","*.cmake","Makefile","meson.build","conanfile.*","vcpkg.json","compile_commands.json","README*","*.md"] {
+        tb.add("meta", g)?;
+    }
+    Ok(tb.select("code").select("meta").build()?)
+}
+
+/// Исключения только для индекса, синтаксис .gitignore (third_party/, generated/, ...).
+/// Порядок: сначала встроенный список каталогов (is_skipped_dir) — их не вернуть и через `!`;
+/// затем .gptcliignore, который сильнее .gitignore (`!path` вернёт в индекс игнорируемое git);
+/// последним — --exclude-paths-file. Вложенные .gptcliignore тоже действуют, как .gitignore.
+pub(crate) const IGNORE_FILE: &str = ".gptcliignore";
+
+/// .gptcliignore из корня проекта для тех, кто не обходит дерево (reindex-changed); None — файла нет
+pub(crate) fn load_gptcliignore(root: &Path) -> Option<Gitignore> {
+    let path = root.join(IGNORE_FILE);
+    if !path.is_file() { return None; }
+    let (gi, err) = Gitignore::new(&path);
+    if let Some(e) = err { eprintln!("warning: {IGNORE_FILE}: {e}"); }
+    Some(gi)
+}
+
+/// Каталоги сборки/зависимостей, которые не обходим (поверх .gitignore)
+pub(crate) fn is_skipped_dir(name: &str) -> bool {
+    matches!(name,
+        "build"|"out"|"dist"|"target"|"node_modules"|"__pycache__"|".cache"|".ccls-cache"|".venv"|"venv"
+    ) || name.starts_with("cmake-build-")
+}
+
+/// Файлы с одинаковым sha: оставляем первый по пути, остальные уходят в file_aliases.
+/// Удаление из files каскадно чистит их теги и чанки.
+pub(crate) fn collapse_duplicates(tx: &rusqlite::Transaction, ns: &str) -> Result<usize> {
+    let mut q = tx.prepare(
+        "SELECT id, path, sha FROM files
+          WHERE namespace=?1 AND sha != ''
+            AND sha IN (SELECT sha FROM files WHERE namespace=?1 AND sha != ''
+                         GROUP BY sha HAVING COUNT(*) > 1)
+          ORDER BY sha, path"
+    )?;
+    let rows = q.query_map(params![ns], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut ins_alias = tx.prepare(
+        "INSERT INTO file_aliases(namespace,path,file_id,sha) VALUES(?1,?2,?3,?4)")?;
+    let mut del_file = tx.prepare("DELETE FROM files WHERE id=?1")?;
+
+    let mut collapsed = 0usize;
+    let mut rep: Option<(i64, String)> = None; // (id, sha) представителя текущей группы
+    for (id, path, sha) in rows {
+        match &rep {
+            Some((rep_id, rep_sha)) if *rep_sha == sha => {
+                ins_alias.execute(params![ns, path, rep_id, sha])?;
+                del_file.execute(params![id])?;
+                collapsed += 1;
+            }
+            _ => rep = Some((id, sha)),
+        }
+    }
+    Ok(collapsed)
+}
+
+/// Список исключений: по glob-шаблону (синтаксис .gitignore) на строку, пустые и `#` пропускаем.
+/// Кривой шаблон — ошибка с номером строки, а не молчаливое игнорирование.
+fn load_excludes(root: &Path, file: &Path) -> Result<Gitignore> {
+    let text = std::fs::read_to_string(file).with_context(|| format!("read {}", file.display()))?;
+    let mut b = GitignoreBuilder::new(root);
+    for (i, line) in text.lines().enumerate() {
+        let pat = line.trim();
+        if pat.is_empty() || pat.starts_with('#') { continue; }
+        b.add_line(None, pat).with_context(|| format!("{}:{}: bad pattern `{pat}`", file.display(), i + 1))?;
+    }
+    Ok(b.build()?)
+}
+
+pub(crate) fn sha256_file(p: &Path) -> Result<String> {
     let f = File::open(p).with_context(|| format!("open {}", p.display()))?;
     let mut r = BufReader::new(f);
     let mut hasher = Sha256::new();
@@ -100,7 +296,7 @@ fn sha256_file(p: &Path) -> Result<String> {
 }
 
 // очень лёгкая эвристика; позже заменим языковыми адаптерами
-fn guess_lang(rel: &str) -> &'static str {
+pub(crate) fn guess_lang(rel: &str) -> &'static str {
     let rel = rel.to_ascii_lowercase();
     match () {
         _ if rel.ends_with(".rs") => "rust",
@@ -115,8 +311,7 @@ fn guess_lang(rel: &str) -> &'static str {
     }
 }
 
-fn classify_doc(rel: &str) -> &'static str {
-    println!("Classfying: {}", rel);
+pub(crate) fn classify_doc(rel: &str) -> &'static str {
     let r = rel.to_ascii_lowercase();
     if r.ends_with(".md") || r.starts_with("docs/") { return "docs"; }
     if r == "cmakelists.txt" || r.ends_with(".cmake") || r == "makefile" || r == "meson.build"
@@ -129,3 +324,34 @@ fn classify_doc(rel: &str) -> &'static str {
     }
     "code"
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_all_keeps_order_and_skips_unreadable() {
+        let dir = std::env::temp_dir().join(format!("gptcli-hash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cand = |rel: &str, cached: Option<&str>| Candidate {
+            rel: rel.to_string(), path: dir.join(rel), size: 0, mtime: 0, cached: cached.map(str::to_string),
+        };
+        let mut candidates = Vec::new();
+        for n in 0..20 {
+            let rel = format!("f{n}.c");
+            std::fs::write(dir.join(&rel), format!("int f{n};")).unwrap();
+            candidates.push(cand(&rel, None));
+        }
+        candidates.push(cand("missing.c", None));
+        candidates.push(cand("cached.c", Some("abc")));
+        let hashed = hash_all(candidates);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rels: Vec<&str> = hashed.iter().map(|(c, _)| c.rel.as_str()).collect();
+        assert_eq!(rels[..3], ["f0.c", "f1.c", "f2.c"]);
+        assert_eq!(rels[20..], ["missing.c", "cached.c"]);
+        assert_eq!(hashed[0].1.as_deref(), Some(format!("{:x}", Sha256::digest(b"int f0;")).as_str()));
+        assert_eq!(hashed[20].1, None);
+        assert_eq!(hashed[21].1.as_deref(), Some("abc"));
+    }
+}
@@ -0,0 +1,353 @@
+use anyhow::{bail, Result};
+use clap::Args;
+use rusqlite::{params, Connection};
+use std::{collections::HashMap, path::Path};
+
+use crate::{commands::{chunk, rerank::{self, RerankOpts}}, db::open_db, embeddings, fs as ufs, hyperlink, llm::CallOpts, state::ProjectState};
+
+/// Найденный чанк; score — bm25 (меньше — лучше), косинус или оценка --rerank 0–10 (больше — лучше)
+pub(crate) struct Hit {
+    pub(crate) namespace: String,
+    pub(crate) path: String,
+    pub(crate) begin_line: i64,
+    pub(crate) end_line: i64,
+    pub(crate) kind: String,
+    pub(crate) symbol: Option<String>,
+    pub(crate) part: Option<(i64, i64)>,
+    pub(crate) lang: String,
+    pub(crate) sha: String,
+    pub(crate) text: String,
+    pub(crate) score: f64,
+}
+
+impl Hit {
+    /// Символ для вывода; часть порезанного символа — `foo [2/3]`
+    pub(crate) fn symbol_label(&self) -> Option<String> {
+        let sym = self.symbol.as_deref()?;
+        Some(match self.part {
+            Some((i, n)) => format!("{sym} [{i}/{n}]"),
+            None => sym.to_string(),
+        })
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SearchArgs {
+    pub query: String,
+    /// Ранжировать по косинусной близости эмбеддингов вместо FTS5
+    #[arg(long)] pub semantic: bool,
+    /// Сколько чанков показать
+    #[arg(long, default_value_t = 10)] pub k: usize,
+    /// Модель эмбеддингов для запроса (та же, что при index --embed)
+    #[arg(long)] pub embed_model: Option<String>,
+    /// Искать во всех namespace БД; у каждого результата — метка [namespace]
+    #[arg(long)] pub all_namespaces: bool,
+    /// text или grep — строки `path:line:col:текст` для quickfix / :grep
+    #[arg(long, default_value = "text")] pub format: String,
+    /// Вместо тела чанка — комментарий над ним и объявление (API без реализации); для --format text
+    #[arg(long)] pub signatures_only: bool,
+    /// Вес совпадения запроса с именем символа чанка (FTS): bm25 × (1 + W × доля слов запроса
+    /// в частях имени); 0 — чистый bm25
+    #[arg(long, default_value_t = 1.0)] pub name_boost: f64,
+    #[command(flatten)] pub rerank: RerankOpts,
+}
+
+pub async fn run(a: SearchArgs) -> Result<()> {
+    let SearchArgs { query, semantic, k, embed_model, all_namespaces, format, signatures_only, name_boost, rerank: rr } = a;
+    let grep = match format.as_str() {
+        "text" => false,
+        "grep" => true,
+        other => bail!("unknown format '{other}': text or grep"),
+    };
+    let root = ufs::detect_project_root()?;
+    let active = ProjectState::load(&root)?.active_namespace();
+    let ns = (!all_namespaces).then_some(active.as_str());
+    let conn = open_db(&root)?;
+
+    let pool = rr.pool(k);
+    let mut hits = if semantic {
+        semantic_search(&root, &conn, ns, &query, pool, &embeddings::model(embed_model)).await?
+    } else {
+        fts_search(&conn, ns, &query, pool, &[], name_boost)?
+    };
+    if rr.rerank && !hits.is_empty() {
+        hits = rerank::rerank(&root, &conn, &query, hits, k, &rr, &CallOpts::default()).await?;
+    }
+    if hits.is_empty() {
+        eprintln!("— nothing found");
+        return Ok(());
+    }
+    if grep {
+        for h in &hits {
+            let (line, col, text) = first_match(h, &query);
+            println!("{}:{line}:{col}:{}", h.path, text.trim_end());
+        }
+        return Ok(());
+    }
+    if signatures_only { to_signatures(&root, &mut hits); }
+    for h in &hits {
+        let sym = h.symbol_label().unwrap_or_else(|| "-".into());
+        let label = if all_namespaces { format!("[{}] ", h.namespace) } else { String::new() };
+        let loc = hyperlink::path_line(&root, &h.path, h.begin_line, &format!("{}:{}-{}", h.path, h.begin_line, h.end_line));
+        println!("{:>8.3}  {label}{loc}  {} {sym}", h.score, h.kind);
+        // сигнатура короткая — целиком; тело — первые две строки
+        let shown = if signatures_only { usize::MAX } else { 2 };
+        for line in h.text.lines().filter(|l| !l.trim().is_empty()).take(shown) {
+            println!("          {}", line.trim_end());
+        }
+    }
+    Ok(())
+}
+
+/// Запрос пользователя → выражение FTS5: каждое слово фразой в кавычках (пунктуация вроде ::
+/// не ломает синтаксис), слова через OR — ранжирование bm25 поднимет чанки с большим числом совпадений
+pub(crate) fn fts_query(query: &str) -> String {
+    query.split_whitespace()
+        .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// --signatures-only: текст чанка → doc-комментарий прямо над ним (из файла на диске, если он
+/// читается) и объявление без тела (chunk::declaration). Продолжения порезанного символа
+/// (части 2..n) объявления не содержат — отбрасываем
+pub(crate) fn to_signatures(root: &Path, hits: &mut Vec<Hit>) {
+    hits.retain(|h| h.part.is_none_or(|(i, _)| i == 1));
+    let mut files: HashMap<String, Option<String>> = HashMap::new();
+    for h in hits.iter_mut() {
+        let file = files.entry(h.path.clone())
+            .or_insert_with(|| ufs::read_text_sanitized(&root.join(&h.path)).ok());
+        let doc = file.as_deref().and_then(|text| {
+            let (b, e) = chunk::comment_blocks(text, &h.lang).into_iter().find(|&(_, e)| e == h.begin_line - 1)?;
+            Some(text.lines().skip(b as usize - 1).take((e - b + 1) as usize).collect::<Vec<_>>().join("\n"))
+        });
+        let decl = chunk::declaration(&h.text, &h.lang);
+        h.text = match doc {
+            Some(doc) => format!("{doc}\n{decl}"),
+            None => decl,
+        };
+    }
+}
+
+/// Первое в чанке вхождение слова запроса: (строка файла, байтовая колонка с 1, текст строки).
+/// Слова и границы — как у токенизатора FTS5 unicode61 (буквы и цифры), без учёта регистра.
+/// Нет совпадения (--semantic) — первая непустая строка чанка, колонка 1
+fn first_match<'a>(h: &'a Hit, query: &str) -> (i64, usize, &'a str) {
+    let terms = query_terms(query);
+    for (i, line) in h.text.lines().enumerate() {
+        if let Some(col) = terms.iter().filter_map(|t| find_word(line, t)).min() {
+            return (h.begin_line + i as i64, col + 1, line);
+        }
+    }
+    let (i, line) = h.text.lines().enumerate().find(|(_, l)| !l.trim().is_empty()).unwrap_or((0, ""));
+    (h.begin_line + i as i64, 1, line)
+}
+
+/// Байтовое смещение слова `term` (уже в нижнем регистре) целиком, не части идентификатора
+fn find_word(line: &str, term: &str) -> Option<usize> {
+    line.char_indices().find_map(|(i, _)| {
+        if line[..i].chars().next_back().is_some_and(char::is_alphanumeric) { return None; }
+        let end = i + match_len(&line[i..], term)?;
+        (!line[end..].chars().next().is_some_and(char::is_alphanumeric)).then_some(i)
+    })
+}
+
+/// Длина в байтах префикса `s`, который в нижнем регистре равен `term`
+fn match_len(s: &str, term: &str) -> Option<usize> {
+    let mut want = term.chars().peekable();
+    for (j, c) in s.char_indices() {
+        if want.peek().is_none() { return Some(j); }
+        for l in c.to_lowercase() {
+            if want.next() != Some(l) { return None; }
+        }
+    }
+    want.peek().is_none().then_some(s.len())
+}
+
+/// `ns` — None: все namespace БД; `kinds` — только чанки этих видов (chunks.kind); пусто — все.
+/// `name_boost` > 0 — из FTS берём пул побольше и пересчитываем score (name_boost_hits)
+pub(crate) fn fts_search(
+    conn: &Connection, ns: Option<&str>, query: &str, k: usize, kinds: &[String], name_boost: f64,
+) -> Result<Vec<Hit>> {
+    let expr = fts_query(query);
+    if expr.is_empty() { bail!("empty query"); }
+    // json_each: список видов одним параметром, без сборки IN (?, ?, ...)
+    let kinds_json = serde_json::to_string(kinds)?;
+    let mut q = conn.prepare(
+        "SELECT f.path, c.begin_line, c.end_line, c.kind, c.symbol, c.text, bm25(fts_chunks), c.part, c.parts, f.namespace,
+                COALESCE(f.lang_guess,''), COALESCE(c.sha,'')
+           FROM fts_chunks JOIN chunks c ON c.id=fts_chunks.rowid
+           JOIN files f ON f.id=c.file_id
+          WHERE fts_chunks MATCH ?1 AND (?2 IS NULL OR f.namespace=?2)
+            AND (json_array_length(?4)=0 OR c.kind IN (SELECT value FROM json_each(?4)))
+          ORDER BY bm25(fts_chunks) LIMIT ?3")?;
+    let pool = if name_boost > 0.0 { (k * NAME_BOOST_POOL).max(50) } else { k };
+    let mut hits = q.query_map(params![expr, ns, pool as i64, kinds_json], |r| Ok(Hit {
+        path: r.get(0)?, begin_line: r.get(1)?, end_line: r.get(2)?,
+        kind: r.get(3)?, symbol: r.get(4)?, text: r.get(5)?, score: r.get(6)?,
+        part: part_of(r.get(7)?, r.get(8)?), namespace: r.get(9)?, lang: r.get(10)?, sha: r.get(11)?,
+    }))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    if name_boost > 0.0 {
+        name_boost_hits(&mut hits, query, name_boost);
+        hits.truncate(k);
+    }
+    Ok(hits)
+}
+
+/// Во сколько раз больше кандидатов FTS брать под --name-boost: символ с именем из запроса
+/// может стоять в чистом bm25 далеко за k
+const NAME_BOOST_POOL: usize = 5;
+
+/// Слова запроса — как у токенизатора FTS5 (буквы и цифры), в нижнем регистре
+fn query_terms(query: &str) -> Vec<String> {
+    query.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Части имени символа: компоненты FQN (`net::TlsClient` → net, tlsclient) и их слова
+/// по camelCase и snake_case (tls, client), в нижнем регистре
+fn name_parts(symbol: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    for comp in symbol.split(|c: char| !c.is_alphanumeric() && c != '_').filter(|c| !c.is_empty()) {
+        out.push(comp.to_lowercase().replace('_', ""));
+        let mut word = String::new();
+        let mut prev_lower = false;
+        for c in comp.chars() {
+            if (c == '_' || (c.is_uppercase() && prev_lower)) && !word.is_empty() {
+                out.push(std::mem::take(&mut word));
+            }
+            if c != '_' { word.extend(c.to_lowercase()); }
+            prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        }
+        if !word.is_empty() { out.push(word); }
+    }
+    out.sort();
+    out.dedup();
+    out
+}
+
+/// bm25 отрицательный (меньше — лучше): score × (1 + w × m), m — доля слов запроса среди частей
+/// имени символа, поднимает «find function X» над чанками, где X только упоминается. Сортировка устойчива
+fn name_boost_hits(hits: &mut [Hit], query: &str, w: f64) {
+    let terms = query_terms(query);
+    if terms.is_empty() { return; }
+    for h in hits.iter_mut() {
+        let Some(sym) = &h.symbol else { continue };
+        let parts = name_parts(sym);
+        let matched = terms.iter().filter(|t| parts.contains(t)).count();
+        h.score *= 1.0 + w * matched as f64 / terms.len() as f64;
+    }
+    hits.sort_by(|a, b| a.score.total_cmp(&b.score));
+}
+
+fn part_of(part: Option<i64>, parts: Option<i64>) -> Option<(i64, i64)> {
+    part.zip(parts)
+}
+
+/// Косинус по всем векторам namespace в памяти: для индекса одного проекта это миллисекунды
+pub(crate) async fn semantic_search(root: &Path, conn: &Connection, ns: Option<&str>, query: &str, k: usize, model: &str) -> Result<Vec<Hit>> {
+    let (total, stale): (i64, i64) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(CASE WHEN v.chunk_id IS NULL OR v.model != ?2 OR v.sha != COALESCE(c.sha,'') THEN 1 ELSE 0 END),0)
+           FROM chunks c JOIN files f ON f.id=c.file_id
+           LEFT JOIN chunk_vectors v ON v.chunk_id=c.id
+          WHERE (?1 IS NULL OR f.namespace=?1)", params![ns, model], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    if total > 0 && stale == total {
+        bail!("no embeddings for {model} in {}; run index --embed", ns.unwrap_or("any namespace"));
+    }
+    if stale > 0 {
+        eprintln!("— {stale}/{total} chunks have no up-to-date embedding ({model}); index --embed refreshes them");
+    }
+
+    let qv = embeddings::embed_texts(root, "search", model, vec![query.to_string()]).await?
+        .pop().unwrap_or_default();
+    let mut q = conn.prepare(
+        "SELECT f.path, c.begin_line, c.end_line, c.kind, c.symbol, c.text, v.vec, c.part, c.parts, f.namespace,
+                COALESCE(f.lang_guess,''), COALESCE(c.sha,'')
+           FROM chunk_vectors v JOIN chunks c ON c.id=v.chunk_id
+           JOIN files f ON f.id=c.file_id
+          WHERE (?1 IS NULL OR f.namespace=?1) AND v.model=?2 AND v.sha=COALESCE(c.sha,'')")?;
+    let mut hits = q.query_map(params![ns, model], |r| {
+        let blob: Vec<u8> = r.get(6)?;
+        Ok(Hit {
+            path: r.get(0)?, begin_line: r.get(1)?, end_line: r.get(2)?,
+            kind: r.get(3)?, symbol: r.get(4)?, text: r.get(5)?,
+            part: part_of(r.get(7)?, r.get(8)?), namespace: r.get(9)?, lang: r.get(10)?, sha: r.get(11)?,
+            score: embeddings::cosine(&qv, &embeddings::from_blob(&blob)) as f64,
+        })
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(k);
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(begin_line: i64, text: &str) -> Hit {
+        Hit {
+            namespace: "p@main".into(), path: "src/a.rs".into(), begin_line, end_line: begin_line + 9,
+            kind: "function".into(), symbol: None, part: None, lang: "rust".into(), sha: String::new(), text: text.into(), score: 0.0,
+        }
+    }
+
+    #[test]
+    fn grep_column_is_first_whole_word_match() {
+        let h = hit(10, "// domain setup\nfn run() {\n    let x = Main::new();\n}");
+        // «domain» не совпадение для main; колонка — байтовая, с 1
+        assert_eq!(first_match(&h, "main"), (12, 13, "    let x = Main::new();"));
+        assert_eq!(first_match(&h, "new main"), (12, 13, "    let x = Main::new();"));
+        assert_eq!(first_match(&h, "net::run"), (11, 4, "fn run() {"));
+    }
+
+    #[test]
+    fn grep_falls_back_to_first_non_empty_line() {
+        let h = hit(5, "\n  impl Foo {}\n");
+        assert_eq!(first_match(&h, "bar"), (6, 1, "  impl Foo {}"));
+    }
+
+    fn scored(path: &str, symbol: Option<&str>, score: f64) -> Hit {
+        Hit { symbol: symbol.map(str::to_string), path: path.into(), score, ..hit(1, "") }
+    }
+
+    #[test]
+    fn name_parts_split_fqn_camel_and_snake() {
+        assert_eq!(name_parts("net::TlsClient::handshake"), ["client", "handshake", "net", "tls", "tlsclient"]);
+        assert_eq!(name_parts("parse_range"), ["parse", "parserange", "range"]);
+        assert_eq!(name_parts("HTTPServer2"), ["httpserver2"]);
+    }
+
+    #[test]
+    fn name_boost_lifts_symbol_named_like_query() {
+        // bm25 выше у вызывающих: parse_range в них упоминается чаще, чем в самой функции
+        let mut hits = vec![
+            scored("src/cli.rs", Some("main"), -4.0),
+            scored("src/explain.rs", Some("run"), -3.5),
+            scored("src/range.rs", Some("parse_range"), -2.5),
+            scored("README.md", None, -3.0),
+        ];
+        name_boost_hits(&mut hits, "parse_range", 1.0);
+        let order: Vec<(&str, f64)> = hits.iter().map(|h| (h.path.as_str(), h.score)).collect();
+        assert_eq!(order, vec![("src/range.rs", -5.0), ("src/cli.rs", -4.0), ("src/explain.rs", -3.5), ("README.md", -3.0)]);
+    }
+
+    #[test]
+    fn name_boost_partial_match_and_zero_weight() {
+        let mut hits = vec![scored("a", Some("TlsClient::handshake"), -2.0), scored("b", Some("connect"), -3.0)];
+        // «tls retry»: совпало одно слово из двух — ×1.5 при W=1, ×1 при W=0
+        name_boost_hits(&mut hits, "tls retry", 1.0);
+        assert_eq!(hits[0].score, -3.0);
+        let mut hits = vec![scored("a", Some("TlsClient::handshake"), -2.0)];
+        name_boost_hits(&mut hits, "tls retry", 0.0);
+        assert_eq!(hits[0].score, -2.0);
+    }
+
+    #[test]
+    fn grep_matches_non_ascii_case_insensitively() {
+        assert_eq!(find_word("// Запуск сервера", "сервера"), Some(16));
+        assert_eq!(find_word("// ЗАПУСК", "запуск"), Some(3));
+        assert_eq!(find_word("запуски", "запуск"), None);
+    }
+}
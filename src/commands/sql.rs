@@ -0,0 +1,83 @@
+use anyhow::{bail, Result};
+use rusqlite::types::ValueRef;
+
+use crate::{db::open_db_readonly, fs as ufs};
+
+pub fn run(query: String, json: bool) -> Result<()> {
+    check_read_only(&query)?;
+    let root = ufs::detect_project_root()?;
+    let conn = open_db_readonly(&root)?;
+
+    let mut stmt = conn.prepare(&query)?;
+    let cols: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+    let mut rows = stmt.query([])?;
+
+    let mut out: Vec<Vec<serde_json::Value>> = Vec::new();
+    while let Some(r) = rows.next()? {
+        let mut row = Vec::with_capacity(cols.len());
+        for i in 0..cols.len() {
+            row.push(to_json(r.get_ref(i)?));
+        }
+        out.push(row);
+    }
+
+    if json {
+        let objs: Vec<serde_json::Value> = out.into_iter()
+            .map(|row| serde_json::Value::Object(cols.iter().cloned().zip(row).collect()))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&objs)?);
+    } else {
+        print_table(&cols, &out);
+    }
+    Ok(())
+}
+
+// Простая проверка: одна инструкция, начинается с SELECT/EXPLAIN (WITH ... SELECT тоже).
+// Основная защита — БД открыта только на чтение.
+fn check_read_only(query: &str) -> Result<()> {
+    let q = query.trim().trim_end_matches(';').trim();
+    if q.contains(';') {
+        bail!("only a single statement is allowed");
+    }
+    let first = q.split_whitespace().next().unwrap_or("").to_ascii_uppercase();
+    if !matches!(first.as_str(), "SELECT" | "EXPLAIN" | "WITH") {
+        bail!("only SELECT/EXPLAIN queries are allowed, got: {first}");
+    }
+    Ok(())
+}
+
+pub(crate) fn to_json(v: ValueRef) -> serde_json::Value {
+    match v {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => f.into(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into(),
+        ValueRef::Blob(b) => format!("<blob {} bytes>", b.len()).into(),
+    }
+}
+
+fn print_table(cols: &[String], rows: &[Vec<serde_json::Value>]) {
+    let cell = |v: &serde_json::Value| match v {
+        serde_json::Value::Null => "NULL".to_string(),
+        serde_json::Value::String(s) => s.replace('\n', "\\n"),
+        other => other.to_string(),
+    };
+    let cells: Vec<Vec<String>> = rows.iter().map(|r| r.iter().map(cell).collect()).collect();
+    let mut widths: Vec<usize> = cols.iter().map(|c| c.chars().count()).collect();
+    for r in &cells {
+        for (i, c) in r.iter().enumerate() {
+            widths[i] = widths[i].max(c.chars().count());
+        }
+    }
+    let line = |vals: &[String]| {
+        vals.iter().enumerate()
+            .map(|(i, v)| format!("{:<w$}", v, w = widths[i]))
+            .collect::<Vec<_>>().join(" | ")
+    };
+    println!("{}", line(cols));
+    println!("{}", widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>().join("-+-"));
+    for r in &cells {
+        println!("{}", line(r));
+    }
+    eprintln!("— {} rows", cells.len());
+}
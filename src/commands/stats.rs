@@ -1,16 +1,19 @@
 use anyhow::Result;
 use rusqlite::params;
 use std::fs;
-use crate::{db::open_db, fs as ufs, state::ProjectState};
+use crate::{db::open_db, fs as ufs, pricing, state::ProjectState};
 
-pub fn run() -> Result<()> {
+pub fn run(all_namespaces: bool) -> Result<()> {
     let root = ufs::detect_project_root()?;
     let st = ProjectState::load(&root)?;
-    let ns = &st.namespace;
+    let ns = &st.active_namespace();
     let conn = open_db(&root)?;
+    if all_namespaces {
+        return run_all(&conn, ns);
+    }
 
     // --- размеры и числа
-    let db_path = root.join(".gptcli/index.sqlite");
+    let db_path = crate::db::db_path(&root);
     let db_bytes = fs::metadata(&db_path).map(|m| m.len()).unwrap_or(0);
 
     let (files_total, bytes_total):(i64,i64) = conn.query_row(
@@ -21,8 +24,8 @@ pub fn run() -> Result<()> {
 
     let (indexed_ok, pending):(i64,i64) = conn.query_row(
         "SELECT \
-           SUM(CASE WHEN indexed_sha IS NOT NULL AND indexed_sha = sha THEN 1 ELSE 0 END), \
-           SUM(CASE WHEN indexed_sha IS NULL OR indexed_sha != sha THEN 1 ELSE 0 END) \
+           COALESCE(SUM(CASE WHEN indexed_sha IS NOT NULL AND indexed_sha = sha THEN 1 ELSE 0 END),0), \
+           COALESCE(SUM(CASE WHEN indexed_sha IS NULL OR indexed_sha != sha THEN 1 ELSE 0 END),0) \
          FROM files WHERE namespace=?1",
         params![ns],
         |r| Ok((r.get(0)?, r.get(1)?))
@@ -50,6 +53,29 @@ pub fn run() -> Result<()> {
         |r| Ok((r.get(0)?, r.get(1)?))
     )?;
 
+    // --- FTS: общий на всю БД (не по namespace). Размер — через dbstat, если он собран,
+    // иначе по блокам fts_chunks_data; покрытие — docsize (строка на документ) против chunks
+    let fts_bytes: i64 = conn.query_row(
+        "SELECT COALESCE(SUM(pgsize),0) FROM dbstat WHERE name LIKE 'fts_chunks%'", [], |r| r.get(0))
+        .or_else(|_| conn.query_row("SELECT COALESCE(SUM(length(block)),0) FROM fts_chunks_data", [], |r| r.get(0)))?;
+    let (all_chunks, fts_docs, fts_orphans): (i64, i64, i64) = conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM chunks),
+                (SELECT COUNT(*) FROM fts_chunks_docsize d JOIN chunks c ON c.id=d.id),
+                (SELECT COUNT(*) FROM fts_chunks_docsize d WHERE d.id NOT IN (SELECT id FROM chunks))",
+        [], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
+
+    // --- расход LLM по usage_log этого namespace
+    let mut usage_stmt = conn.prepare(
+        "SELECT model, COUNT(*), SUM(input_tokens), SUM(output_tokens), SUM(total_tokens)
+           FROM usage_log WHERE namespace=?1 GROUP BY model")?;
+    let (mut llm_calls, mut llm_tokens, mut llm_cost) = (0i64, 0i64, Some(0.0));
+    for row in usage_stmt.query_map(params![ns], |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?, r.get::<_, i64>(2)?, r.get::<_, i64>(3)?, r.get::<_, i64>(4)?)))? {
+        let (model, calls, input, output, total) = row?;
+        llm_calls += calls;
+        llm_tokens += total;
+        llm_cost = llm_cost.zip(pricing::cost_for(&model, input, output)).map(|(a, b)| a + b);
+    }
+
     // --- распределение по doc_kind
     let mut kinds_stmt = conn.prepare(
         "SELECT doc_kind, COUNT(*) FROM files WHERE namespace=?1 GROUP BY doc_kind ORDER BY COUNT(*) DESC"
@@ -62,10 +88,16 @@ pub fn run() -> Result<()> {
 
     // --- вывод
     println!("Namespace: {}", ns);
+    if files_total == 0 {
+        println!("Namespace is empty (unknown or not scanned yet); see stats --all-namespaces");
+    }
     println!("DB: {} ({})", db_path.display(), human_size(db_bytes));
     println!("Files: {} total | {} indexed | {} pending | size ~{}",
         files_total, indexed_ok, pending, human_size(bytes_total as u64)
     );
+    if indexed_ok > 0 && pending > 0 {
+        println!("Index: PARTIAL ({pending} files not indexed yet, run index)");
+    }
     if !kinds.is_empty() {
         print!("Kinds: ");
         for (i,(k,c)) in kinds.iter().enumerate() {
@@ -76,12 +108,52 @@ pub fn run() -> Result<()> {
     }
     println!("Tags: {}", tags_cnt);
     println!("Chunks: {} (text ~{})", chunks_cnt, human_size(chunk_text_bytes as u64));
+    let coverage = if all_chunks > 0 { fts_docs as f64 * 100.0 / all_chunks as f64 } else { 100.0 };
+    println!("FTS: ~{} | coverage {coverage:.1}% ({fts_docs}/{all_chunks} chunks, all namespaces)", human_size(fts_bytes as u64));
+    if fts_docs < all_chunks || fts_orphans > 0 {
+        println!("FTS: out of sync ({} missing, {fts_orphans} stale); rebuild: INSERT INTO fts_chunks(fts_chunks) VALUES('rebuild')",
+            all_chunks - fts_docs);
+    }
+    if llm_calls > 0 {
+        println!("LLM: {llm_calls} calls, {llm_tokens} tokens, estimated cost: {}", pricing::fmt_cost(llm_cost));
+    }
     println!("Last seen_at: {}", seen_max.map(fmt_ts).unwrap_or_else(|| "-".into()));
     println!("Last indexed_at: {}", indexed_max.map(fmt_ts).unwrap_or_else(|| "-".into()));
 
     Ok(())
 }
 
+/// Все namespace одной БД (ветки, подпроекты) в одной таблице; активный помечен `*`
+fn run_all(conn: &rusqlite::Connection, active: &str) -> Result<()> {
+    let mut q = conn.prepare(
+        "SELECT f.namespace, COUNT(*),
+                SUM(CASE WHEN f.indexed_sha IS NOT NULL AND f.indexed_sha = f.sha THEN 1 ELSE 0 END),
+                COALESCE(SUM((SELECT COUNT(*) FROM tags t WHERE t.file_id=f.id)),0),
+                COALESCE(SUM((SELECT COUNT(*) FROM chunks c WHERE c.file_id=f.id)),0),
+                COALESCE(SUM((SELECT SUM(length(c.text)) FROM chunks c WHERE c.file_id=f.id)),0),
+                MAX(f.indexed_at)
+           FROM files f GROUP BY f.namespace ORDER BY f.namespace")?;
+    let rows = q.query_map([], |r| Ok((
+        r.get::<_, String>(0)?, r.get::<_, i64>(1)?, r.get::<_, i64>(2)?, r.get::<_, i64>(3)?,
+        r.get::<_, i64>(4)?, r.get::<_, i64>(5)?, r.get::<_, Option<i64>>(6)?,
+    )))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    if rows.is_empty() {
+        println!("No namespaces yet (run scan)");
+        return Ok(());
+    }
+    let w = rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max("namespace".len()) + 2;
+    println!("{:<w$} {:>7} {:>8} {:>7} {:>7} {:>10}  last indexed", "  namespace", "files", "indexed", "tags", "chunks", "text");
+    let mut total = (0i64, 0i64, 0i64, 0i64, 0i64);
+    for (ns, files, indexed, tags, chunks, bytes, last) in &rows {
+        let name = if ns == active { format!("* {ns}") } else { format!("  {ns}") };
+        println!("{name:<w$} {files:>7} {indexed:>8} {tags:>7} {chunks:>7} {:>10}  {}",
+            human_size(*bytes as u64), last.map(fmt_ts).unwrap_or_else(|| "-".into()));
+        total = (total.0 + files, total.1 + indexed, total.2 + tags, total.3 + chunks, total.4 + bytes);
+    }
+    println!("{:<w$} {:>7} {:>8} {:>7} {:>7} {:>10}", "  total", total.0, total.1, total.2, total.3, human_size(total.4 as u64));
+    Ok(())
+}
+
 // --- утилиты
 
 fn human_size(n: u64) -> String {
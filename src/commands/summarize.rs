@@ -4,32 +4,177 @@ use rusqlite::params;
 use std::{collections::BTreeMap, fs};
 
 use async_openai::{
-    types::responses::{Content, ContentType, CreateResponseArgs, Input, InputContent, InputItem, InputMessageArgs, InputMessageType, InputText, Response, Role}, Client
+    types::responses::{Content, CreateResponseArgs, Input, InputContent, InputItem, InputMessageArgs, InputMessageType, Response, Role}
 //    types::{ ResponseInput, InputContent, ResponseCreateArgs }
 };
 
-use time::OffsetDateTime;
-
-use crate::{db::open_db, fs as ufs, state::ProjectState};
+use crate::{db::open_db, fs as ufs, llm::{self, CallOpts, GenOpts}, state::ProjectState};
 
 // Главная точка
-pub fn run(build_limit: usize) -> Result<()> {
+pub fn run(opts: FactsOpts) -> Result<()> {
+    print!("{}", collect_facts(&opts)?);
+    Ok(())
+}
+
+/// Что собирать в факты: общие для `summarize` и `summarize --llm --facts-from-index`
+#[derive(Debug, Clone, Default)]
+pub struct FactsOpts {
+    pub build_limit: usize,
+    /// [RECENT ACTIVITY]: последние N коммитов
+    pub git_log: Option<usize>,
+    pub focus: Focus,
+}
+
+/// --focus: подсистема для STRUCTURE/ENTRYPOINTS/TODOs — scope (`net::`, `net::http`) или
+/// префикс пути (`src/net`); TECH/BUILD остаются общими для проекта
+#[derive(Debug, Clone, Default)]
+pub struct Focus(pub Option<String>);
+
+impl Focus {
+    fn matches(&self, path: &str, fqn: Option<&str>) -> bool {
+        let Some(f) = self.0.as_deref() else { return true };
+        if f.contains("::") {
+            let base = f.trim_end_matches("::");
+            fqn.is_some_and(|s| s == base || s.strip_prefix(base).is_some_and(|rest| rest.starts_with("::")))
+        } else {
+            let dir = f.trim_end_matches('/');
+            path == dir || path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+        }
+    }
+}
+
+/// Секционный текст фактов под любую LLM: то, что печатает `summarize` и читает `summarize --llm`
+fn collect_facts(opts: &FactsOpts) -> Result<String> {
+    let FactsOpts { build_limit, git_log, focus } = opts;
     let root = ufs::detect_project_root()?;
     let st = ProjectState::load(&root)?;
-    let ns = &st.namespace;
+    let ns = &st.active_namespace();
     let conn = open_db(&root)?;
 
-    let build = collect_build_facts(&conn, &root, ns, build_limit)?;
-    let entry = collect_entry_points(&conn, ns)?;
-    let stru  = collect_structure(&conn, ns)?;
-    let todos = collect_todos(&conn, ns, 20)?;
+    let tech  = collect_tech(&conn, &root, ns)?;
+    let build = collect_build_facts(&conn, &root, ns, *build_limit)?;
+    let entry = collect_entry_points(&conn, ns, focus)?;
+    let stru  = collect_structure(&conn, ns, focus)?;
+    let todos = collect_todos(&conn, ns, 20, focus)?;
 
-    // Секционный текст под любую LLM
-    println!("[BUILD]\n{}\n", build.trim());
-    println!("[ENTRYPOINTS]\n{}\n", entry.trim());
-    println!("[STRUCTURE]\n{}\n", stru.trim());
-    println!("[TODOs]\n{}\n", todos.trim());
-    Ok(())
+    let mut out = String::new();
+    if let Some(repo) = repo_identity(&st) {
+        out.push_str(&format!("[REPO]\n{}\n\n", repo.trim()));
+    }
+    if let Some(f) = &focus.0 {
+        out.push_str(&format!("[FOCUS]\n{f} (STRUCTURE/ENTRYPOINTS/TODOs — только эта часть проекта)\n\n"));
+    }
+    out.push_str(&format!("[TECH]\n{}\n\n", tech.trim()));
+    out.push_str(&format!("[BUILD]\n{}\n\n", build.trim()));
+    out.push_str(&format!("[ENTRYPOINTS]\n{}\n\n", entry.trim()));
+    out.push_str(&format!("[STRUCTURE]\n{}\n\n", stru.trim()));
+    out.push_str(&format!("[TODOs]\n{}\n\n", todos.trim()));
+    // не git-репозиторий или пустая история — секцию опускаем
+    if let Some(n) = *git_log && let Some(activity) = collect_git_log(&root, st.subproject.as_deref(), n) {
+        out.push_str(&format!("[RECENT ACTIVITY]\n{}\n\n", activity.trim()));
+    }
+    Ok(out)
+}
+
+// --- RECENT ACTIVITY: последние N коммитов (в подпроекте — только его) + суммарный shortstat
+fn collect_git_log(root: &std::path::Path, subproject: Option<&str>, n: usize) -> Option<String> {
+    let n_arg = format!("-{n}");
+    let mut args = vec!["log", "--oneline", "--no-decorate", n_arg.as_str()];
+    if let Some(d) = subproject { args.extend(["--", d]); }
+    let subjects = ufs::git_output(root, &args)?;
+
+    // " 3 files changed, 10 insertions(+), 2 deletions(-)" по каждому коммиту → суммы
+    let mut args = vec!["log", "--format=", "--shortstat", n_arg.as_str()];
+    if let Some(d) = subproject { args.extend(["--", d]); }
+    let (mut files, mut ins, mut del) = (0u64, 0u64, 0u64);
+    for line in ufs::git_output(root, &args).unwrap_or_default().lines() {
+        for part in line.split(',') {
+            let mut it = part.split_whitespace();
+            let (Some(num), Some(what)) = (it.next().and_then(|x| x.parse::<u64>().ok()), it.next()) else { continue };
+            match what {
+                w if w.starts_with("file") => files += num,
+                w if w.starts_with("insertion") => ins += num,
+                w if w.starts_with("deletion") => del += num,
+                _ => {}
+            }
+        }
+    }
+    let count = subjects.lines().count();
+    Some(format!("last {count} commits: {files} file changes, +{ins}/-{del} lines\n{subjects}"))
+}
+
+// --- REPO: откуда проект (remote, ветка, шаблон web-ссылок); None — нечего сообщить
+fn repo_identity(st: &ProjectState) -> Option<String> {
+    let url = st.remote_url.as_deref()?;
+    let mut out = vec![format!("remote: {url}")];
+    if let Some(b) = &st.default_branch { out.push(format!("default branch: {b}")); }
+    if let Some(t) = st.web_template() { out.push(format!("web links: {t}")); }
+    Some(out.join("\n"))
+}
+
+// --- TECH: основной язык (по lang_guess) + вероятные фреймворки (манифесты и каталоги)
+const FRAMEWORKS: &[(&str, &[&str])] = &[
+    // (имя, маркеры в манифестах / сегменты путей; сравнение без учёта регистра)
+    ("Qt",         &["find_package(qt", "qt5", "qt6", "qt_add_"]),
+    ("Boost",      &["boost"]),
+    ("GoogleTest", &["gtest", "googletest"]),
+    ("Catch2",     &["catch2"]),
+    ("fmt",        &["find_package(fmt", "fmt::fmt"]),
+    ("spdlog",     &["spdlog"]),
+    ("OpenSSL",    &["openssl"]),
+    ("Protobuf",   &["protobuf"]),
+    ("gRPC",       &["grpc"]),
+    ("Eigen",      &["eigen3", "eigen"]),
+    ("OpenCV",     &["opencv"]),
+    ("React",      &["\"react\"", "react-dom"]),
+];
+
+fn collect_tech(conn: &rusqlite::Connection, root: &std::path::Path, ns: &str) -> Result<String> {
+    let mut out = Vec::<String>::new();
+
+    // распределение языков по исходникам
+    let mut q = conn.prepare(
+        "SELECT lang_guess, COUNT(*) FROM files
+          WHERE namespace=?1 AND doc_kind IN ('code','tests') AND lang_guess != 'other'
+          GROUP BY lang_guess ORDER BY COUNT(*) DESC"
+    )?;
+    let langs = q.query_map(params![ns], |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let total: i64 = langs.iter().map(|(_, c)| c).sum();
+    if let Some((primary, cnt)) = langs.first() {
+        out.push(format!("primary language: {primary} ({}% of {total} source files)", cnt * 100 / total));
+        let rest = langs.iter().skip(1).map(|(l, c)| format!("{l}:{c}")).collect::<Vec<_>>();
+        if !rest.is_empty() { out.push(format!("other languages: {}", rest.join(", "))); }
+    }
+
+    // фреймворки: маркеры в текстах манифестов + каталоги с такими именами
+    let mut q = conn.prepare("SELECT path, doc_kind FROM files WHERE namespace=?1 ORDER BY path")?;
+    let files = q.query_map(params![ns], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let manifests: String = files.iter()
+        .filter(|(_, k)| k == "manifest")
+        .map(|(p, _)| fs::read_to_string(root.join(p)).unwrap_or_default().to_ascii_lowercase())
+        .collect::<Vec<_>>().join("\n");
+    let dirs: std::collections::BTreeSet<String> = files.iter()
+        .flat_map(|(p, _)| {
+            let mut parts: Vec<String> = p.split('/').map(|s| s.to_ascii_lowercase()).collect();
+            parts.pop(); // имя файла
+            parts
+        })
+        .collect();
+
+    let mut found = Vec::new();
+    for (name, needles) in FRAMEWORKS {
+        let in_manifest = needles.iter().any(|n| manifests.contains(n));
+        let in_dirs = needles.iter().any(|n| dirs.contains(*n));
+        if in_manifest || in_dirs {
+            let src = match (in_manifest, in_dirs) { (true, true) => "manifest+dirs", (true, false) => "manifest", _ => "dirs" };
+            found.push(format!("{name} ({src})"));
+        }
+    }
+    if !found.is_empty() { out.push(format!("frameworks: {}", found.join(", "))); }
+
+    if out.is_empty() { Ok("— (нет просканированных исходников)".into()) } else { Ok(out.join("\n")) }
 }
 
 // --- BUILD: вытягиваем только сигнальные директивы из CMake
@@ -78,12 +223,12 @@ fn normalize_cmake_line(s: &str) -> String {
 }
 
 // --- ENTRYPOINTS: main() + примитивные маркеры тестов
-fn collect_entry_points(conn: &rusqlite::Connection, ns: &str) -> Result<String> {
+fn collect_entry_points(conn: &rusqlite::Connection, ns: &str, focus: &Focus) -> Result<String> {
     let mut out = Vec::<String>::new();
 
     // main из ctags
     let mut q = conn.prepare(
-        "SELECT f.path, t.line FROM tags t
+        "SELECT f.path, t.line, CASE WHEN t.scope IS NULL THEN t.name ELSE t.scope||'::'||t.name END FROM tags t
            JOIN files f ON f.id=t.file_id
           WHERE f.namespace=?1 AND t.kind='function' AND t.name='main'
           ORDER BY f.path, t.line"
@@ -92,15 +237,19 @@ fn collect_entry_points(conn: &rusqlite::Connection, ns: &str) -> Result<String>
     while let Some(r) = rows.next()? {
         let path: String = r.get(0)?;
         let line: i64 = r.get(1)?;
+        let fqn: String = r.get(2)?;
+        if !focus.matches(&path, Some(&fqn)) { continue; }
         out.push(format!("main: {}:{}", path, line));
     }
 
     // простые тестовые маркеры из chunks (если уже есть)
-    let tests_cnt: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM chunks c JOIN files f ON f.id=c.file_id
-         WHERE f.namespace=?1 AND (c.text LIKE '%TEST(' OR c.text LIKE '%TEST_CASE(' OR c.text LIKE '%Catch::Session%')",
-        params![ns], |r| r.get(0)
-    ).unwrap_or(0);
+    let mut q = conn.prepare(
+        "SELECT f.path, c.symbol FROM chunks c JOIN files f ON f.id=c.file_id
+         WHERE f.namespace=?1 AND (c.text LIKE '%TEST(' OR c.text LIKE '%TEST_CASE(' OR c.text LIKE '%Catch::Session%')")?;
+    let tests_cnt = q.query_map(params![ns], |r| Ok((r.get::<_, String>(0)?, r.get::<_, Option<String>>(1)?)))?
+        .filter_map(|r| r.ok())
+        .filter(|(path, sym)| focus.matches(path, sym.as_deref()))
+        .count();
     if tests_cnt > 0 {
         out.push(format!("tests: ~{} chunks with test markers (gtest/catch2)", tests_cnt));
     }
@@ -109,20 +258,29 @@ fn collect_entry_points(conn: &rusqlite::Connection, ns: &str) -> Result<String>
 }
 
 // --- STRUCTURE: счётчики по каталогам и видам символов
-fn collect_structure(conn: &rusqlite::Connection, ns: &str) -> Result<String> {
+fn collect_structure(conn: &rusqlite::Connection, ns: &str, focus: &Focus) -> Result<String> {
     // агрегируем по директориям (верхний уровень / два уровня)
     let mut q = conn.prepare(
-        "SELECT f.path, t.kind FROM tags t
+        "SELECT f.path, t.kind, COALESCE(t.access,''), CASE WHEN t.scope IS NULL THEN t.name ELSE t.scope||'::'||t.name END FROM tags t
            JOIN files f ON f.id=t.file_id
           WHERE f.namespace=?1"
     )?;
     let mut rows = q.query(params![ns])?;
 
     let mut per_dir: BTreeMap<String, (i64,i64,i64)> = BTreeMap::new(); // dir -> (classes, functions, namespaces)
+    let mut api: BTreeMap<String, (i64,i64)> = BTreeMap::new(); // dir -> (public, private+protected) членов классов
     while let Some(r) = rows.next()? {
         let path: String = r.get(0)?;
         let kind: String = r.get(1)?;
+        let access: String = r.get(2)?;
+        let fqn: String = r.get(3)?;
+        if !focus.matches(&path, Some(&fqn)) { continue; }
         let dir = short_dir(&path);
+        match access.as_str() {
+            "public" => api.entry(dir.clone()).or_default().0 += 1,
+            "private" | "protected" => api.entry(dir.clone()).or_default().1 += 1,
+            _ => {}
+        }
         let e = per_dir.entry(dir).or_insert((0,0,0));
         match kind.as_str() {
             "class" | "struct" => e.0 += 1,
@@ -137,7 +295,14 @@ fn collect_structure(conn: &rusqlite::Connection, ns: &str) -> Result<String> {
     let mut out = Vec::new();
     for (i,(d,(c,f,n))) in v.into_iter().enumerate() {
         if i>=10 { break; }
-        out.push(format!("{d}: classes={c}, funcs={f}, namespaces={n}"));
+        match api.get(&d) {
+            Some((p, i)) => out.push(format!("{d}: classes={c}, funcs={f}, namespaces={n}, public={p}, internal={i}")),
+            None => out.push(format!("{d}: classes={c}, funcs={f}, namespaces={n}")),
+        }
+    }
+    let (public, internal) = api.values().fold((0, 0), |(p, i), (dp, di)| (p + dp, i + di));
+    if public + internal > 0 {
+        out.push(format!("class members: {public} public (API surface), {internal} private/protected"));
     }
     if out.is_empty() { Ok("— нет тегов (запусти index)".into()) } else { Ok(out.join("\n")) }
 }
@@ -150,19 +315,22 @@ fn short_dir(path: &str) -> String {
 }
 
 // --- TODOs: простая выборка из чанков
-fn collect_todos(conn: &rusqlite::Connection, ns: &str, limit: usize) -> Result<String> {
+fn collect_todos(conn: &rusqlite::Connection, ns: &str, limit: usize, focus: &Focus) -> Result<String> {
     let mut q = conn.prepare(
-        "SELECT f.path, c.begin_line
+        "SELECT f.path, c.begin_line, c.symbol
            FROM chunks c JOIN files f ON f.id=c.file_id
           WHERE f.namespace=?1 AND (c.text LIKE '%TODO%' OR c.text LIKE '%FIXME%' OR c.text LIKE '%HACK%')
-          ORDER BY f.path, c.begin_line LIMIT ?2"
+          ORDER BY f.path, c.begin_line"
     )?;
-    let mut rows = q.query(params![ns, limit as i64])?;
+    let mut rows = q.query(params![ns])?;
     let mut out = Vec::<String>::new();
     while let Some(r) = rows.next()? {
         let path: String = r.get(0)?;
         let line: i64 = r.get(1)?;
+        let symbol: Option<String> = r.get(2)?;
+        if !focus.matches(&path, symbol.as_deref()) { continue; }
         out.push(format!("{}:{}", path, line));
+        if out.len() >= limit { break; }
     }
     if out.is_empty() { Ok("— не найдено TODO/FIXME/HACK".into()) } else { Ok(out.join("\n")) }
 }
@@ -170,9 +338,20 @@ fn collect_todos(conn: &rusqlite::Connection, ns: &str, limit: usize) -> Result<
 
 use async_openai::types::{responses::OutputContent};
 
+/// Текст ответа; если текста нет (одни tool calls, reasoning, аннотации) — диагностика вместо
+/// пустой строки, чтобы команда не «успешно» печатала ничего
 pub fn extract_output_text(resp: &Response) -> String {
-    if let Some(t) = resp.output_text.clone() {
-        return t;
+    output_text(resp).unwrap_or_else(|| {
+        let shape = llm::output_shape(resp);
+        eprintln!("⚠ response has no text content ({shape}); see raw response");
+        format!("[ответ модели без текста: {shape}]")
+    })
+}
+
+/// Текст ответа как есть; None — текстовой части нет или она пустая
+pub fn output_text(resp: &Response) -> Option<String> {
+    if let Some(t) = resp.output_text.clone() && !t.trim().is_empty() {
+        return Some(t);
     }
     let mut parts = Vec::new();
     for oc in &resp.output {
@@ -186,7 +365,10 @@ pub fn extract_output_text(resp: &Response) -> String {
                     Content::OutputText(output_text) => {
                         parts.push(output_text.text.clone());
                     },
-                    Content::Refusal(refusal) => todo!(),
+                    // отказ — тоже ответ: печатаем с пометкой; отличить его можно через llm::refusal
+                    Content::Refusal(r) => {
+                        parts.push(format!("[refusal] {}", r.refusal));
+                    },
                     /*
                     // если есть удобный геттер:
                     _ if item.output_text().is_some() => {
@@ -202,18 +384,25 @@ pub fn extract_output_text(resp: &Response) -> String {
             }
         }
     }
-    parts.join("\n")
+    let text = parts.join("\n");
+    (!text.trim().is_empty()).then_some(text)
 }
 
-pub async fn run_llm(model: String, max_output: usize, system_file: Option<String>, facts_path: String) -> Result<()> {
+/// `facts_from_index` — собрать факты здесь же (как `summarize` с теми же флагами) вместо файла
+pub async fn run_llm(model: String, max_output: usize, system_file: Option<String>, facts_path: String,
+    facts_from_index: Option<FactsOpts>, gen_opts: &GenOpts, call: &CallOpts) -> Result<()> {
+    let model = llm::resolve_model(&model);
     // 1) читаем данные
-    let facts = fs::read_to_string(&facts_path)
-        .with_context(|| format!("read {}", facts_path))?;
+    let facts = if let Some(opts) = &facts_from_index {
+        collect_facts(opts)?
+    } else {
+        fs::read_to_string(&facts_path).with_context(|| format!("read {}", facts_path))?
+    };
     let system = if let Some(p) = system_file {
         fs::read_to_string(&p).context("read system_file")?
     } else {
         // дефолтная короткая инструкция
-        "Ты — технический обзорщик C/C++ проектов. Пиши кратко и структурировано. Не выдумывай: опирайся только на предоставленные секции [BUILD]/[ENTRYPOINTS]/[STRUCTURE]/[TODOs]. Вывод: 1) краткое описание; 2) сборка (список); 3) модули и ответственность; 4) внешние зависимости и зачем; 5) тесты/инфраструктура; 6) риски/технический долг (списком).".to_string()
+        "Ты — технический обзорщик C/C++ проектов. Пиши кратко и структурировано. Не выдумывай: опирайся только на предоставленные секции [REPO]/[TECH]/[BUILD]/[ENTRYPOINTS]/[STRUCTURE]/[TODOs]/[RECENT ACTIVITY]. Вывод: 1) краткое описание; 2) сборка (список); 3) модули и ответственность; 4) внешние зависимости и зачем; 5) тесты/инфраструктура; 6) риски/технический долг (списком).".to_string()
     };
 
 
@@ -221,7 +410,7 @@ pub async fn run_llm(model: String, max_output: usize, system_file: Option<Strin
         InputMessageArgs::default()
             .kind(InputMessageType::Message)                // можно опустить: Default
             .role(Role::System)
-            .content(InputContent::TextInput(system.clone())) // <-- оборачиваем текст
+            .content(InputContent::TextInput(gen_opts.system_prompt(&system))) // <-- оборачиваем текст
             .build()?
     );
 
@@ -229,7 +418,7 @@ pub async fn run_llm(model: String, max_output: usize, system_file: Option<Strin
         InputMessageArgs::default()
             .role(Role::User)
             .content(InputContent::TextInput(
-                format!("Ниже факты о проекте (BUILD/ENTRYPOINTS/STRUCTURE/TODOs). Подготовь обзор.\n{}", &facts)
+                format!("Ниже факты о проекте (TECH/BUILD/ENTRYPOINTS/STRUCTURE/TODOs). Подготовь обзор.\n{}", &facts)
             ))
             .build()?
     );
@@ -238,36 +427,43 @@ pub async fn run_llm(model: String, max_output: usize, system_file: Option<Strin
     // 2) соберём объект запроса (Responses API)
     let input :Vec<InputItem> = vec![ system_msg, user_msg ];
 
-    let args = CreateResponseArgs::default()
-        .model(model.clone())
-        .max_output_tokens(max_output as u32)
-        .input(Input::Items(input))
-        .build()?;
+    let mut args = CreateResponseArgs::default();
+    args.model(model.clone())
+        .max_output_tokens(llm::clamp_max_output(&model, max_output as u32))
+        .input(Input::Items(input));
+    gen_opts.apply(&mut args);
+    let args = args.build()?;
 
-    // 3) сохраним сырой запрос в /tmp
-    let ts = OffsetDateTime::now_utc().unix_timestamp();
-    let req_path = format!("/tmp/gptcli-request-{}-{}.json", model, ts);
-    let resp_path = format!("/tmp/gptcli-response-{}-{}.json", model, ts);
+    // 3) сохраним сырой запрос (GPTCLI_LOG_DIR или .gptcli/logs)
+    let root = ufs::detect_project_root()?;
+    let (req_path, resp_path) = llm::request_log_paths(&root, "summarize", &model)?;
     fs::write(&req_path, serde_json::to_vec_pretty(&args)?)?;
+    if call.dry_run {
+        llm::print_dry_run(&args, &req_path, call);
+        return Ok(());
+    }
 
     // 4) вызов
-    let client = Client::new(); // использует OPENAI_API_KEY из окружения
-    let resp = client.responses().create(args).await?;
+    // OPENAI_API_KEY из окружения; повтор того же запроса — из кэша ответов
+    llm::check_budget(&root, &args, call)?;
+    let (resp, cached) = match llm::create_response(&root, args, call).await {
+        Ok(r) => r,
+        Err(e) => {
+            llm::report_failed_request(&req_path, &gen_opts.system_prompt(&system), &facts);
+            return Err(e.into());
+        }
+    };
 
     // 5) лог сырых ответов
     fs::write(&resp_path, serde_json::to_vec_pretty(&resp)?)?;
+    llm::warn_if_incomplete(&resp);
 
     // 6) вытащим текст и usage
     let text = extract_output_text(&resp);
 
-    // usage может отсутствовать — учитываем это
-    let (pt, ct, tt) = if let Some(u) = resp.usage {
-        (u.input_tokens, u.output_tokens, u.total_tokens)
-    } else { (0,0,0) };
-
     println!("{text}\n");
-    eprintln!("— usage: prompt={pt}, completion={ct}, total={tt}");
-    eprintln!("— raw request: {req_path}");
-    eprintln!("— raw response: {resp_path}");
+    llm::report_usage(&root, "summarize", &model, &resp, cached);
+    eprintln!("— raw request: {}", req_path.display());
+    eprintln!("— raw response: {}", resp_path.display());
     Ok(())
 }
@@ -0,0 +1,59 @@
+use anyhow::Result;
+use rusqlite::types::Value;
+
+use crate::{db::open_db_readonly, fs as ufs, hyperlink, state::ProjectState};
+
+/// Список тегов из индекса. pattern — по name или scope::name: с `*`/`?`/`[` — GLOB,
+/// с `%` — LIKE как есть, иначе подстрока (LIKE, без учёта регистра ASCII)
+pub fn run(pattern: Option<String>, kind: Option<String>, file: Option<String>, limit: usize) -> Result<()> {
+    let root = ufs::detect_project_root()?;
+    let st = ProjectState::load(&root)?;
+    let ns = st.active_namespace();
+    let conn = open_db_readonly(&root)?;
+
+    let fqn = "(CASE WHEN t.scope IS NULL THEN t.name ELSE t.scope||'::'||t.name END)";
+    let mut sql = format!(
+        "SELECT t.kind, {fqn}, f.path, t.line, COALESCE(t.signature,'')
+           FROM tags t JOIN files f ON f.id=t.file_id
+          WHERE f.namespace=?1");
+    let mut args: Vec<Value> = vec![Value::Text(ns)];
+    if let Some(p) = pattern {
+        let (op, p) = if p.contains(['*', '?', '[']) { ("GLOB", p) }
+                      else if p.contains('%') { ("LIKE", p) }
+                      else { ("LIKE", format!("%{p}%")) };
+        args.push(Value::Text(p));
+        sql.push_str(&format!(" AND (t.name {op} ?{n} OR {fqn} {op} ?{n})", n = args.len()));
+    }
+    if let Some(k) = kind {
+        args.push(Value::Text(k));
+        sql.push_str(&format!(" AND t.kind=?{}", args.len()));
+    }
+    if let Some(f) = file {
+        args.push(Value::Text(f));
+        sql.push_str(&format!(" AND f.path=?{}", args.len()));
+    }
+    args.push(Value::Integer(limit as i64));
+    sql.push_str(&format!(" ORDER BY f.path, t.line LIMIT ?{}", args.len()));
+
+    let mut q = conn.prepare(&sql)?;
+    let rows = q.query_map(rusqlite::params_from_iter(args), |r| Ok((
+        r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?,
+        r.get::<_, Option<i64>>(3)?, r.get::<_, String>(4)?,
+    )))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    if rows.is_empty() {
+        eprintln!("— no symbols matched");
+        return Ok(());
+    }
+    let kw = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+    let fw = rows.iter().map(|r| r.1.len()).max().unwrap_or(0);
+    for (kind, fqn, path, line, sig) in &rows {
+        let line = line.unwrap_or(0);
+        let loc = hyperlink::path_line(&root, path, line, &format!("{path}:{line}"));
+        let line = format!("{kind:<kw$}  {fqn:<fw$}  {loc}  {sig}");
+        println!("{}", line.trim_end());
+    }
+    if rows.len() == limit {
+        eprintln!("— showing first {limit}; use --limit for more");
+    }
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use anyhow::{bail, Result};
+use clap::Subcommand;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::{db::open_db, fs as ufs, plan::Plan, state::ProjectState};
+
+/// Диалоги: ask --thread продолжает текущий (current_thread_id в state.json)
+#[derive(Subcommand, Debug)]
+pub enum ThreadCmd {
+    /// Начать новый диалог и сделать его текущим
+    New {
+        #[arg(long)] title: Option<String>,
+        /// Показать, что изменится, ничего не записывая
+        #[arg(long)] dry_run: bool,
+    },
+    /// Диалоги активного namespace; текущий помечен `*`
+    List,
+    /// Сделать текущим диалог с этим id
+    Switch {
+        id: String,
+        /// Показать, что изменится, ничего не записывая
+        #[arg(long)] dry_run: bool,
+    },
+}
+
+impl ThreadCmd {
+    pub fn dry_run(&self) -> bool {
+        matches!(self, ThreadCmd::New { dry_run: true, .. } | ThreadCmd::Switch { dry_run: true, .. })
+    }
+}
+
+pub fn run(cmd: ThreadCmd) -> Result<()> {
+    let root = ufs::detect_project_root()?;
+    let mut st = ProjectState::load(&root)?;
+    let ns = st.active_namespace();
+    let conn = open_db(&root)?;
+    match cmd {
+        ThreadCmd::New { title, dry_run } => {
+            if dry_run {
+                let mut plan = Plan::new("thread new");
+                plan.add("threads created", 1, &title.into_iter().collect::<Vec<_>>());
+                plan.add("current thread changed (state.json)", 1, &[]);
+                plan.print();
+                return Ok(());
+            }
+            let now = OffsetDateTime::now_utc();
+            // короткий id: хватает, чтобы набирать руками в thread switch
+            let id = format!("{:x}", Sha256::digest(format!("{ns}{}", now.unix_timestamp_nanos())))[..8].to_string();
+            conn.execute("INSERT INTO threads(id, namespace, title, created_at) VALUES(?1, ?2, ?3, ?4)",
+                params![id, ns, title, now.unix_timestamp()])?;
+            st.current_thread_id = Some(id.clone());
+            st.save()?;
+            println!("thread {id} started{}", title.map(|t| format!(": {t}")).unwrap_or_default());
+        }
+        ThreadCmd::List => {
+            let mut q = conn.prepare(
+                "SELECT t.id, COALESCE(t.title,''), t.created_at, (SELECT COUNT(*) FROM messages m WHERE m.thread_id=t.id)
+                   FROM threads t WHERE t.namespace=?1 ORDER BY t.created_at")?;
+            let rows = q.query_map(params![ns], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, i64>(2)?, r.get::<_, i64>(3)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            if rows.is_empty() {
+                println!("No threads yet (thread new)");
+            }
+            for (id, title, created, messages) in rows {
+                let mark = if st.current_thread_id.as_deref() == Some(id.as_str()) { '*' } else { ' ' };
+                let created = OffsetDateTime::from_unix_timestamp(created).map(|t| t.date().to_string()).unwrap_or_default();
+                println!("{mark} {id}  {created}  {messages:>3} msgs  {title}");
+            }
+        }
+        ThreadCmd::Switch { id, dry_run } => {
+            if thread_namespace(&conn, &id)?.as_deref() != Some(ns.as_str()) {
+                bail!("unknown thread '{id}' in namespace {ns} (see thread list)");
+            }
+            if dry_run {
+                let mut plan = Plan::new("thread switch");
+                let changed = st.current_thread_id.as_deref() != Some(id.as_str());
+                plan.add("current thread changed (state.json)", changed as usize, &[id]);
+                plan.print();
+                return Ok(());
+            }
+            st.current_thread_id = Some(id.clone());
+            st.save()?;
+            println!("current thread: {id}");
+        }
+    }
+    Ok(())
+}
+
+fn thread_namespace(conn: &Connection, id: &str) -> Result<Option<String>> {
+    Ok(conn.query_row("SELECT namespace FROM threads WHERE id=?1", params![id], |r| r.get(0)).optional()?)
+}
+
+/// Текущий диалог для ask --thread; ошибка, если его нет или он из другого namespace
+pub(crate) fn current(conn: &Connection, st: &ProjectState) -> Result<String> {
+    let Some(id) = st.current_thread_id.clone() else { bail!("no current thread: run thread new") };
+    let ns = st.active_namespace();
+    if thread_namespace(conn, &id)?.as_deref() != Some(ns.as_str()) {
+        bail!("current thread '{id}' does not belong to namespace {ns}: run thread new or thread switch");
+    }
+    Ok(id)
+}
+
+/// История диалога по порядку: (role, content), role — "user" | "assistant"
+pub(crate) fn history(conn: &Connection, thread_id: &str) -> Result<Vec<(String, String)>> {
+    let mut q = conn.prepare("SELECT role, content FROM messages WHERE thread_id=?1 ORDER BY id")?;
+    let rows = q.query_map(params![thread_id], |r| Ok((r.get(0)?, r.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Дописать реплику; usage_total — только у ответа модели
+pub(crate) fn append(conn: &Connection, thread_id: &str, role: &str, content: &str, usage_total: Option<u32>) -> Result<()> {
+    conn.execute("INSERT INTO messages(thread_id, role, content, ts, usage_total) VALUES(?1, ?2, ?3, ?4, ?5)",
+        params![thread_id, role, content, OffsetDateTime::now_utc().unix_timestamp(), usage_total])?;
+    Ok(())
+}
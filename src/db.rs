@@ -1,9 +1,42 @@
-use anyhow::Result;
-use rusqlite::{Connection, params};
+use anyhow::{bail, Context, Result};
+use rusqlite::{Connection, OpenFlags};
 use std::path::{Path, PathBuf};
+use std::sync::{atomic::{AtomicBool, Ordering}, OnceLock};
+
+/// Копия БД перед миграцией схемы (--no-backup выключает)
+static MIGRATION_BACKUP: AtomicBool = AtomicBool::new(true);
+
+pub fn set_migration_backup(enabled: bool) {
+    MIGRATION_BACKUP.store(enabled, Ordering::Relaxed);
+}
+
+/// --dry-run: БД не создаётся и не мигрирует (и без копии .bak-v{N}) — на диск ничего не пишется
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+}
+
+/// Другой файл БД вместо .gptcli/index.sqlite на весь процесс (bench работает на копии)
+static DB_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn redirect_db(path: PathBuf) {
+    let _ = DB_OVERRIDE.set(path);
+}
+
+pub fn db_path(project_root: &Path) -> PathBuf {
+    DB_OVERRIDE.get().cloned().unwrap_or_else(|| project_root.join(".gptcli/index.sqlite"))
+}
+
+/// Текущая версия схемы (PRAGMA user_version)
+pub const SCHEMA_VERSION: i64 = 12;
 
 pub fn open_db(project_root: &Path) -> Result<Connection> {
-    let db_path = project_root.join(".gptcli/index.sqlite");
+    let db_path = db_path(project_root);
+    let dry_run = DRY_RUN.load(Ordering::Relaxed);
+    if dry_run && !db_path.exists() {
+        bail!("no index yet ({}); --dry-run does not create it", db_path.display());
+    }
     std::fs::create_dir_all(db_path.parent().unwrap())?;
     let conn = Connection::open(&db_path)?;
     // базовые PRAGMA
@@ -12,19 +45,105 @@ pub fn open_db(project_root: &Path) -> Result<Connection> {
          PRAGMA synchronous=NORMAL;
          PRAGMA foreign_keys=ON;"
     )?;
-    ensure_schema(&conn)?;
+    ensure_schema(&conn, &db_path)?;
+    Ok(conn)
+}
+
+/// Открыть существующую БД только на чтение (без миграций)
+pub fn open_db_readonly(project_root: &Path) -> Result<Connection> {
+    let db_path = db_path(project_root);
+    let conn = Connection::open_with_flags(&db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
     Ok(conn)
 }
 
-fn ensure_schema(conn: &Connection) -> Result<()> {
+fn ensure_schema(conn: &Connection, db_path: &Path) -> Result<()> {
     let v: i64 = conn.query_row("PRAGMA user_version;", [], |r| r.get(0))?;
+    if v < SCHEMA_VERSION && DRY_RUN.load(Ordering::Relaxed) {
+        bail!("index schema v{v} needs migration to v{SCHEMA_VERSION}; run once without --dry-run");
+    }
+    // новая БД (v == 0) терять нечего
+    let backup = if v > 0 && v < SCHEMA_VERSION && MIGRATION_BACKUP.load(Ordering::Relaxed) {
+        Some(backup_before_migration(conn, db_path, v)?)
+    } else { None };
     if v == 0 {
         create_v1(conn)?;
         conn.execute("PRAGMA user_version = 1;", [])?;
     }
+    if v < 2 {
+        migrate_v2(conn)?;
+        conn.execute("PRAGMA user_version = 2;", [])?;
+    }
+    if v < 3 {
+        migrate_v3(conn)?;
+        conn.execute("PRAGMA user_version = 3;", [])?;
+    }
+    if v < 4 {
+        migrate_v4(conn)?;
+        conn.execute("PRAGMA user_version = 4;", [])?;
+    }
+    if v < 5 {
+        migrate_v5(conn)?;
+        conn.execute("PRAGMA user_version = 5;", [])?;
+    }
+    if v < 6 {
+        migrate_v6(conn)?;
+        conn.execute("PRAGMA user_version = 6;", [])?;
+    }
+    if v < 7 {
+        migrate_v7(conn)?;
+        conn.execute("PRAGMA user_version = 7;", [])?;
+    }
+    if v < 8 {
+        migrate_v8(conn)?;
+        conn.execute("PRAGMA user_version = 8;", [])?;
+    }
+    if v < 9 {
+        migrate_v9(conn)?;
+        conn.execute("PRAGMA user_version = 9;", [])?;
+    }
+    if v < 10 {
+        migrate_v10(conn)?;
+        conn.execute("PRAGMA user_version = 10;", [])?;
+    }
+    if v < 11 {
+        migrate_v11(conn)?;
+        conn.execute("PRAGMA user_version = 11;", [])?;
+    }
+    if v < 12 {
+        migrate_v12(conn)?;
+        conn.execute("PRAGMA user_version = 12;", [])?;
+    }
+    if let Some(keep) = backup {
+        remove_old_backups(db_path, &keep);
+        eprintln!("— schema migrated v{v} → v{SCHEMA_VERSION}; backup: {}", keep.display());
+    }
     Ok(())
 }
 
+/// Согласованная копия (VACUUM INTO учитывает WAL, в отличие от копирования файла) рядом с БД:
+/// index.sqlite.bak-v{from}. Миграция упала — откатиться можно, переименовав копию обратно.
+fn backup_before_migration(conn: &Connection, db_path: &Path, from: i64) -> Result<std::path::PathBuf> {
+    let name = db_path.file_name().unwrap_or_default().to_string_lossy();
+    let bak = db_path.with_file_name(format!("{name}.bak-v{from}"));
+    // VACUUM INTO не перезаписывает существующий файл
+    let _ = std::fs::remove_file(&bak);
+    conn.execute("VACUUM INTO ?1", [bak.to_string_lossy()])
+        .with_context(|| format!("backup before migration to {}", bak.display()))?;
+    Ok(bak)
+}
+
+/// После успешной миграции оставляем только свежую копию
+fn remove_old_backups(db_path: &Path, keep: &Path) {
+    let Some(dir) = db_path.parent() else { return };
+    let prefix = format!("{}.bak-v", db_path.file_name().unwrap_or_default().to_string_lossy());
+    for e in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        let p = e.path();
+        if p != keep && e.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = std::fs::remove_file(p);
+        }
+    }
+}
+
 fn create_v1(conn: &Connection) -> Result<()> {
     conn.execute_batch(r#"
     -- файлы, обнаруженные scan'ом
@@ -92,3 +211,146 @@ fn create_v1(conn: &Connection) -> Result<()> {
     "#)?;
     Ok(())
 }
+
+// v2: тип из ctags (typeref) — возвращаемый тип функции / тип поля
+fn migrate_v2(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    ALTER TABLE tags ADD COLUMN type_ref TEXT;
+    -- старые теги type_ref не содержат: форсируем переиндексацию
+    UPDATE files SET indexed_sha = NULL;
+    "#)?;
+    Ok(())
+}
+
+// v3: дубликаты по содержимому (scan --dedup-content)
+fn migrate_v3(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    CREATE TABLE IF NOT EXISTS file_aliases(
+      namespace   TEXT NOT NULL,
+      path        TEXT NOT NULL,                 -- путь дубликата
+      file_id     INTEGER NOT NULL REFERENCES files(id) ON DELETE CASCADE, -- представитель
+      sha         TEXT,
+      UNIQUE(namespace, path)
+    );
+    CREATE INDEX IF NOT EXISTS idx_file_aliases_file ON file_aliases(file_id);
+    "#)?;
+    Ok(())
+}
+
+// v4: причина, по которой файл не удалось проиндексировать (NULL — ок)
+fn migrate_v4(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE files ADD COLUMN index_error TEXT;")?;
+    Ok(())
+}
+
+// v5: оценка размера чанка в токенах (символы/4, как chunk::estimate_tokens)
+fn migrate_v5(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    ALTER TABLE chunks ADD COLUMN tokens INTEGER;
+    UPDATE chunks SET tokens = (length(text) + 3) / 4;
+    "#)?;
+    Ok(())
+}
+
+// v6: видимость члена класса из ctags (public|protected|private; NULL — не член)
+fn migrate_v6(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    ALTER TABLE tags ADD COLUMN access TEXT;
+    -- старые теги access не содержат: форсируем переиндексацию
+    UPDATE files SET indexed_sha = NULL;
+    "#)?;
+    Ok(())
+}
+
+// v7: локальный журнал расхода токенов (строка на вызов LLM)
+fn migrate_v7(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    CREATE TABLE IF NOT EXISTS usage_log(
+      id INTEGER PRIMARY KEY,
+      ts INTEGER NOT NULL,
+      command TEXT NOT NULL,
+      model TEXT NOT NULL,
+      input_tokens INTEGER NOT NULL,
+      output_tokens INTEGER NOT NULL,
+      total_tokens INTEGER NOT NULL,
+      namespace TEXT
+    );
+    CREATE INDEX IF NOT EXISTS idx_usage_log_ts ON usage_log(ts);
+    "#)?;
+    Ok(())
+}
+
+// v8: эмбеддинги чанков; sha — sha чанка на момент эмбеддинга (устарел, если не совпадает)
+fn migrate_v8(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    CREATE TABLE IF NOT EXISTS chunk_vectors(
+      chunk_id INTEGER PRIMARY KEY REFERENCES chunks(id) ON DELETE CASCADE,
+      model TEXT NOT NULL,
+      dim INTEGER NOT NULL,
+      sha TEXT NOT NULL,
+      vec BLOB NOT NULL
+    );
+    "#)?;
+    Ok(())
+}
+
+// v9: диалоги (thread) — история ask --thread по namespace
+fn migrate_v9(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    CREATE TABLE IF NOT EXISTS threads(
+      id TEXT PRIMARY KEY,
+      namespace TEXT NOT NULL,
+      title TEXT,
+      created_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS messages(
+      id INTEGER PRIMARY KEY,
+      thread_id TEXT NOT NULL REFERENCES threads(id) ON DELETE CASCADE,
+      role TEXT NOT NULL,
+      content TEXT NOT NULL,
+      ts INTEGER NOT NULL,
+      usage_total INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS idx_messages_thread ON messages(thread_id, id);
+    "#)?;
+    Ok(())
+}
+
+// v10: кэш ответов LLM; key — sha256 запроса, response_json — ответ целиком (для --json/--strict)
+fn migrate_v10(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    CREATE TABLE IF NOT EXISTS response_cache(
+      key TEXT PRIMARY KEY,
+      model TEXT NOT NULL,
+      created_at INTEGER NOT NULL,
+      text TEXT NOT NULL,
+      usage_json TEXT,
+      response_json TEXT NOT NULL
+    );
+    "#)?;
+    Ok(())
+}
+
+// v11: часть чанка, порезанного окнами (--max-chunk-lines / --chunk-by-tokens): part из parts, с 1
+fn migrate_v11(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    ALTER TABLE chunks ADD COLUMN part INTEGER;
+    ALTER TABLE chunks ADD COLUMN parts INTEGER;
+    "#)?;
+    Ok(())
+}
+
+// v12: оценки search/ask --rerank; sha — chunks.sha: изменился чанк — оценка не подходит
+fn migrate_v12(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+    CREATE TABLE IF NOT EXISTS rerank_cache(
+      query TEXT NOT NULL,
+      sha TEXT NOT NULL,
+      model TEXT NOT NULL,
+      score REAL NOT NULL,
+      created_at INTEGER NOT NULL,
+      PRIMARY KEY(query, sha, model)
+    );
+    "#)?;
+    Ok(())
+}
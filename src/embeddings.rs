@@ -0,0 +1,88 @@
+use anyhow::{Context, Result};
+use async_openai::{types::{CreateEmbeddingRequestArgs, EmbeddingInput}, Client};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+use crate::{db::open_db, fs as ufs, llm, state::ProjectState};
+
+/// Модель эмбеддингов: флаг, иначе GPTCLI_EMBED_MODEL, иначе text-embedding-3-small
+pub fn model(flag: Option<String>) -> String {
+    flag.or_else(|| std::env::var("GPTCLI_EMBED_MODEL").ok())
+        .unwrap_or_else(|| "text-embedding-3-small".into())
+}
+
+/// `index --embed[=MODEL]`: после индексации досчитать векторы активного namespace
+pub async fn run(flag: String) -> Result<()> {
+    let root = ufs::detect_project_root()?;
+    let ns = ProjectState::load(&root)?.active_namespace();
+    let conn = open_db(&root)?;
+    let model = model(Some(flag).filter(|m| !m.is_empty()));
+    let n = update(&root, &conn, &ns, &model).await?;
+    println!("embeddings: {n} chunks embedded ({model})");
+    Ok(())
+}
+
+/// Чанков на один запрос к /embeddings
+const BATCH: usize = 64;
+/// Обрезка текста чанка (символы; ~8k токенов — лимит входа моделей эмбеддингов)
+const MAX_CHARS: usize = 30_000;
+
+/// Векторы для texts; расход токенов пишется в usage_log под `command`, как у вызовов LLM
+pub async fn embed_texts(root: &Path, command: &str, model: &str, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+    let req = CreateEmbeddingRequestArgs::default()
+        .model(model)
+        .input(EmbeddingInput::StringArray(texts))
+        .build()?;
+    let mut resp = Client::new().embeddings().create(req).await.context("embeddings request")?;
+    llm::record_tokens(root, command, model, resp.usage.prompt_tokens, 0, resp.usage.total_tokens);
+    // порядок в data задаёт index, а не позиция
+    resp.data.sort_by_key(|e| e.index);
+    Ok(resp.data.into_iter().map(|e| e.embedding).collect())
+}
+
+/// Досчитать векторы чанков namespace: нет вектора, другая модель или sha чанка сменился
+/// с момента эмбеддинга. Возвращает число пересчитанных чанков.
+pub async fn update(root: &Path, conn: &Connection, ns: &str, model: &str) -> Result<usize> {
+    let mut q = conn.prepare(
+        "SELECT c.id, COALESCE(c.sha,''), c.text FROM chunks c
+           JOIN files f ON f.id=c.file_id
+           LEFT JOIN chunk_vectors v ON v.chunk_id=c.id
+          WHERE f.namespace=?1 AND (v.chunk_id IS NULL OR v.model != ?2 OR v.sha != COALESCE(c.sha,''))
+          ORDER BY c.id")?;
+    let todo = q.query_map(params![ns, model], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut ins = conn.prepare(
+        "INSERT OR REPLACE INTO chunk_vectors(chunk_id, model, dim, sha, vec) VALUES(?1,?2,?3,?4,?5)")?;
+    for (i, batch) in todo.chunks(BATCH).enumerate() {
+        eprintln!("embedding {}/{} chunks ({model})", (i * BATCH + batch.len()), todo.len());
+        let texts = batch.iter().map(|(_, _, t)| {
+            // пустая строка API не принимает
+            let t: String = t.chars().take(MAX_CHARS).collect();
+            if t.trim().is_empty() { " ".to_string() } else { t }
+        }).collect();
+        let vecs = embed_texts(root, "embed", model, texts).await?;
+        anyhow::ensure!(vecs.len() == batch.len(), "embeddings: got {} vectors for {} inputs", vecs.len(), batch.len());
+        for ((id, sha, _), v) in batch.iter().zip(vecs) {
+            ins.execute(params![id, model, v.len() as i64, sha, to_blob(&v)])?;
+        }
+    }
+    Ok(todo.len())
+}
+
+pub fn to_blob(v: &[f32]) -> Vec<u8> {
+    v.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub fn from_blob(b: &[u8]) -> Vec<f32> {
+    b.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect()
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut na, mut nb) = (0f32, 0f32, 0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        na += x * x;
+        nb += y * y;
+    }
+    if na == 0.0 || nb == 0.0 { 0.0 } else { dot / (na.sqrt() * nb.sqrt()) }
+}
@@ -1,20 +1,184 @@
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
 
-/// Определяем корень проекта: git → cwd
+static TEXT_ENCODING: OnceLock<&'static Encoding> = OnceLock::new();
+static PROJECT_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Определяем корень проекта: --root / GPTCLI_ROOT → git → cwd.
+/// Результат запоминается на весь процесс: git не запускается на каждый вызов
 pub fn detect_project_root() -> Result<PathBuf> {
-    if let Ok(out) = Command::new("git").args(["rev-parse", "--show-toplevel"]).output() {
-        if out.status.success() {
-            let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
-            if !s.is_empty() { return Ok(PathBuf::from(s)); }
-        }
+    if let Some(root) = PROJECT_ROOT.get() {
+        return Ok(root.clone());
+    }
+    let root = match Command::new("git").args(["rev-parse", "--show-toplevel"]).output() {
+        Ok(out) if out.status.success() && !out.stdout.trim_ascii().is_empty() =>
+            PathBuf::from(String::from_utf8_lossy(&out.stdout).trim()),
+        _ => std::env::current_dir()?,
+    };
+    Ok(PROJECT_ROOT.get_or_init(|| root).clone())
+}
+
+/// Явный корень проекта (--root / GPTCLI_ROOT) вместо git rev-parse
+pub fn set_project_root(dir: &str) -> Result<()> {
+    let root = std::fs::canonicalize(dir).with_context(|| format!("project root {dir}"))?;
+    if !root.is_dir() { anyhow::bail!("project root {} is not a directory", root.display()); }
+    let _ = PROJECT_ROOT.set(root);
+    Ok(())
+}
+
+/// Вывод git-команды в корне проекта; None — не git или команда упала
+pub fn git_output(root: &Path, args: &[&str]) -> Option<String> {
+    let out = Command::new("git").args(args).current_dir(root).output().ok()?;
+    if !out.status.success() { return None; }
+    let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    (!s.is_empty()).then_some(s)
+}
+
+/// Построчный вывод git-команды; в отличие от git_output пустой вывод — это Some(vec![])
+pub fn git_lines(root: &Path, args: &[&str]) -> Option<Vec<String>> {
+    let out = Command::new("git").args(args).current_dir(root).output().ok()?;
+    if !out.status.success() { return None; }
+    Some(String::from_utf8_lossy(&out.stdout).lines().filter(|l| !l.is_empty()).map(str::to_string).collect())
+}
+
+/// Запись без полуфабрикатов: пишем `<file>.tmp`, fsync, прежнюю версию копируем в `<file>.bak`,
+/// затем атомарный rename поверх оригинала
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    use std::io::Write;
+    let name = path.file_name().context("empty path")?.to_string_lossy().to_string();
+    let tmp = path.with_file_name(format!("{name}.tmp"));
+    {
+        let mut f = std::fs::File::create(&tmp).with_context(|| format!("create {}", tmp.display()))?;
+        f.write_all(data)?;
+        f.sync_all()?;
     }
-    Ok(std::env::current_dir()?)
+    if path.exists() {
+        std::fs::copy(path, path.with_file_name(format!("{name}.bak")))
+            .with_context(|| format!("backup {}", path.display()))?;
+    }
+    std::fs::rename(&tmp, path).with_context(|| format!("rename {} -> {}", tmp.display(), path.display()))?;
+    Ok(())
 }
 
 /// Убедиться, что .gptcli существует
-pub fn ensure_project_dirs(root: &PathBuf) -> Result<()> {
+pub fn ensure_project_dirs(root: &Path) -> Result<()> {
     std::fs::create_dir_all(root.join(".gptcli"))?;
     Ok(())
 }
+
+/// Кодировка исходников для однокодировочных legacy-проектов (--encoding / GPTCLI_ENCODING)
+pub fn set_text_encoding(label: &str) -> Result<()> {
+    let enc = Encoding::for_label(label.trim().as_bytes())
+        .with_context(|| format!("unknown encoding: {label}"))?;
+    let _ = TEXT_ENCODING.set(enc);
+    Ok(())
+}
+
+/// Декодировать в заданной кодировке; None — кодировка не задана (UTF-8 или угадывание, см. decode_text)
+pub fn decode_configured(bytes: &[u8]) -> Option<String> {
+    let enc = TEXT_ENCODING.get()?;
+    let (s, _, _) = enc.decode(bytes);
+    Some(s.into_owned())
+}
+
+/// Текст исходника: заданная кодировка, иначе UTF-8, иначе угаданная legacy-кодировка.
+/// Единственная точка чтения исходников для index/chunk/explain — чтобы кодировку правили в одном месте
+pub fn read_text_sanitized(path: &Path) -> Result<String> {
+    let bytes = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    Ok(decode_text(&bytes))
+}
+
+/// То же для байтов не с диска (git show для explain --rev)
+pub fn decode_text(bytes: &[u8]) -> String {
+    if let Some(s) = decode_configured(bytes) {
+        return s;
+    }
+    // валидный UTF-8 не трогаем
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        return s.to_string();
+    }
+    // UTF-8 с парой битых байтов: многобайтовые символы сохраняем, битое — в U+FFFD
+    let lossy = String::from_utf8_lossy(bytes);
+    let (decoded, broken) = lossy.chars().filter(|c| !c.is_ascii()).fold((0, 0), |(d, b), c| {
+        if c == char::REPLACEMENT_CHARACTER { (d, b + 1) } else { (d + 1, b) }
+    });
+    if decoded > broken {
+        return lossy.into_owned();
+    }
+    match guess_legacy_encoding(bytes) {
+        Some(enc) => enc.decode(bytes).0.into_owned(),
+        None => lossy.into_owned(),
+    }
+}
+
+/// Однобайтовая кодировка для файла, который явно не UTF-8. Кириллица cp1251 идёт словами —
+/// старшие байты (буквы 0xC0..=0xFF, ё/Ё) стоят подряд; акценты latin-1 (café, Müller) —
+/// одиночные среди ASCII. None — старшие байты не похожи на текст (управляющие 0x80..0x9F и т.п.)
+fn guess_legacy_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    let is_letter = |b: u8| b >= 0xC0 || b == 0xA8 || b == 0xB8;
+    let high: Vec<usize> = bytes.iter().enumerate().filter(|(_, b)| **b >= 0x80).map(|(i, _)| i).collect();
+    if high.is_empty() { return None; }
+    let letters = high.iter().filter(|&&i| is_letter(bytes[i])).count();
+    if letters * 10 < high.len() * 8 { return None; }
+    let paired = high.iter()
+        .filter(|&&i| (i > 0 && bytes[i - 1] >= 0x80) || bytes.get(i + 1).is_some_and(|b| *b >= 0x80))
+        .count();
+    Some(if paired * 2 > high.len() { encoding_rs::WINDOWS_1251 } else { encoding_rs::WINDOWS_1252 })
+}
+
+/// Строки [begin, end] (1-based, включительно), каждая с '\n'
+pub fn slice_lines(full: &str, begin: i64, end: i64) -> String {
+    let mut res = String::new();
+    for (idx, line) in full.lines().enumerate() {
+        let ln = (idx as i64) + 1;
+        if ln < begin { continue; }
+        if ln > end { break; }
+        res.push_str(line);
+        res.push('\n');
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_text_keeps_valid_utf8() {
+        let text = "// Привет, мир — café\nfn main() {}\n";
+        assert_eq!(decode_text(text.as_bytes()), text);
+    }
+
+    #[test]
+    fn decode_text_reads_cp1251_file() {
+        let text = "// Подключение к серверу, ёжик\nint main() { return 0; }\n";
+        let (bytes, _, _) = encoding_rs::WINDOWS_1251.encode(text);
+        assert!(std::str::from_utf8(&bytes).is_err());
+        assert_eq!(guess_legacy_encoding(&bytes), Some(encoding_rs::WINDOWS_1251));
+        assert_eq!(decode_text(&bytes), text);
+    }
+
+    #[test]
+    fn decode_text_reads_latin1_accents_as_cp1252() {
+        let text = "// café, Müller\n";
+        let (bytes, _, _) = encoding_rs::WINDOWS_1252.encode(text);
+        assert_eq!(guess_legacy_encoding(&bytes), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(decode_text(&bytes), text);
+    }
+
+    #[test]
+    fn decode_text_utf8_with_broken_byte_stays_utf8() {
+        let mut bytes = "// Привет\n".as_bytes().to_vec();
+        bytes.push(0xFF);
+        assert_eq!(decode_text(&bytes), "// Привет\n\u{FFFD}");
+    }
+
+    #[test]
+    fn guess_legacy_encoding_rejects_binary_like_bytes() {
+        assert_eq!(guess_legacy_encoding(b"plain ascii"), None);
+        assert_eq!(guess_legacy_encoding(&[b'a', 0x81, 0x90, b'b', 0x9F]), None);
+    }
+}
@@ -0,0 +1,33 @@
+use std::{io::IsTerminal, path::Path, sync::OnceLock};
+
+/// Ссылки OSC-8 только для терминала: в пайпе и при TERM=dumb — простой `path:line`
+fn enabled() -> bool {
+    static ON: OnceLock<bool> = OnceLock::new();
+    *ON.get_or_init(|| std::io::stdout().is_terminal() && std::env::var("TERM").map_or(true, |t| t != "dumb"))
+}
+
+/// `label` как кликабельная ссылка на `path:line` (path — относительно корня проекта).
+/// URL — `file://<абсолютный путь>` или шаблон GPTCLI_EDITOR_URL с {path} и {line},
+/// напр. `vscode://file{path}:{line}` ({path} абсолютный, начинается с /)
+pub fn path_line(root: &Path, path: &str, line: i64, label: &str) -> String {
+    if !enabled() { return label.to_string(); }
+    let abs = root.join(path);
+    let abs = abs.to_string_lossy();
+    let url = match std::env::var("GPTCLI_EDITOR_URL") {
+        Ok(t) if !t.is_empty() => t.replace("{path}", &encode(&abs)).replace("{line}", &line.to_string()),
+        _ => format!("file://{}", encode(&abs)),
+    };
+    format!("\x1b]8;;{url}\x1b\\{label}\x1b]8;;\x1b\\")
+}
+
+/// Процентное кодирование пути для URL; `/` и безопасные ASCII-символы остаются как есть
+fn encode(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
@@ -0,0 +1,338 @@
+use async_openai::{
+    error::OpenAIError,
+    types::responses::{
+        Content, CreateResponse, CreateResponseArgs, Input, InputContent, InputItem, OutputContent, Response, Status, Usage,
+    },
+    Client,
+};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use sha2::{Digest, Sha256};
+use std::{path::{Path, PathBuf}, sync::OnceLock};
+use time::OffsetDateTime;
+
+/// Общие параметры генерации для LLM-команд (explain, summarize --llm)
+#[derive(Args, Debug, Clone, Default)]
+pub struct GenOpts {
+    /// Стоп-последовательность (можно указать несколько раз); Responses API её не принимает —
+    /// пока отбрасывается с предупреждением
+    #[arg(long = "stop")]
+    pub stop: Vec<String>,
+    /// Максимум вызовов инструментов за один ответ
+    #[arg(long)]
+    pub max_tool_calls: Option<u32>,
+    /// Язык ответа независимо от языка промпта (напр. English); также GPTCLI_ANSWER_LANGUAGE
+    #[arg(long)]
+    pub answer_language: Option<String>,
+}
+
+impl GenOpts {
+    /// Переносим в запрос то, что поддерживает провайдер; остальное отбрасываем с предупреждением в stderr
+    pub fn apply(&self, args: &mut CreateResponseArgs) {
+        if !self.stop.is_empty() {
+            // Responses API (openai) не принимает stop-последовательности
+            eprintln!("warning: --stop ignored: the Responses API does not support stop sequences ({})", self.stop.join(", "));
+        }
+        if let Some(n) = self.max_tool_calls {
+            args.max_tool_calls(n);
+        }
+    }
+
+    /// system-промпт с явным требованием языка ответа (если он задан)
+    pub fn system_prompt(&self, system: &str) -> String {
+        let lang = self.answer_language.clone().or_else(|| std::env::var("GPTCLI_ANSWER_LANGUAGE").ok());
+        match lang.as_deref().map(str::trim) {
+            Some(l) if !l.is_empty() => format!("{system}\n\nОтвечай на языке: {l}. Имена из кода не переводи."),
+            _ => system.to_string(),
+        }
+    }
+}
+
+/// Псевдонимы моделей для --model: GPTCLI_MODEL_ALIASES="fast=gpt-4.1-mini,smart=gpt-4.1"
+/// (набор меняется вместе с окружением, напр. для локального сервера). Неизвестное имя — как есть.
+pub fn resolve_model(model: &str) -> String {
+    let aliases = std::env::var("GPTCLI_MODEL_ALIASES").unwrap_or_default();
+    let target = aliases.split(',')
+        .filter_map(|e| e.split_once('='))
+        .find(|(alias, _)| alias.trim() == model)
+        .map(|(_, id)| id.trim().to_string());
+    match target {
+        Some(id) if !id.is_empty() => {
+            tracing::debug!(alias = model, model = %id, "model alias resolved");
+            id
+        }
+        _ => model.to_string(),
+    }
+}
+
+/// Потолок max_output_tokens по моделям (префикс имени → лимит); датированные снапшоты
+/// (gpt-4o-2024-08-06) матчатся по самому длинному префиксу
+const MAX_OUTPUT: &[(&str, u32)] = &[
+    ("gpt-3.5-turbo", 4096),
+    ("gpt-4-turbo",   4096),
+    ("gpt-4",         8192),
+    ("gpt-4o",        16384),
+    ("gpt-4.1",       32768),
+    ("gpt-5",         128000),
+    ("o1",            100000),
+    ("o3",            100000),
+    ("o4-mini",       100000),
+];
+
+/// Запрошенный лимит вывода, урезанный до возможностей модели (иначе API ответит 400).
+/// Неизвестная модель — значение как есть.
+pub fn clamp_max_output(model: &str, requested: u32) -> u32 {
+    let cap = MAX_OUTPUT.iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, cap)| *cap);
+    match cap {
+        Some(cap) if requested > cap => {
+            eprintln!("— max-output {requested} exceeds {model} limit, clamped to {cap}");
+            cap
+        }
+        _ => requested,
+    }
+}
+
+/// Ответ, оборванный по лимиту (или иной причине), не должен выглядеть полным
+pub fn warn_if_incomplete(resp: &Response) {
+    if resp.status != Status::Incomplete { return; }
+    let reason = resp.incomplete_details.as_ref().map(|d| d.reason.as_str()).unwrap_or("unknown");
+    if reason == "max_output_tokens" {
+        let limit = resp.max_output_tokens.map(|n| n.to_string()).unwrap_or_else(|| "?".into());
+        eprintln!("⚠ output truncated at {limit} tokens; increase --max-output");
+    } else {
+        eprintln!("⚠ response incomplete: {reason}");
+    }
+}
+
+/// Краткая форма ответа для диагностики: статус и типы элементов output
+pub fn output_shape(resp: &Response) -> String {
+    let kind = |v: serde_json::Value| v.get("type").and_then(|t| t.as_str()).unwrap_or("?").to_string();
+    let items: Vec<String> = resp.output.iter()
+        .map(|oc| serde_json::to_value(oc).map(kind).unwrap_or_else(|_| "?".into()))
+        .collect();
+    let status = serde_json::to_value(&resp.status).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default();
+    format!("status={status}, output=[{}]", items.join(", "))
+}
+
+/// Текст отказа модели, если в ответе есть refusal-часть
+pub fn refusal(resp: &Response) -> Option<String> {
+    resp.output.iter().find_map(|oc| match oc {
+        OutputContent::Message(msg) => msg.content.iter().find_map(|c| match c {
+            Content::Refusal(r) => Some(r.refusal.clone()),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+/// Строка расхода токенов; cached — сколько входных токенов провайдер взял из кэша префикса.
+/// Кэш срабатывает на стабильном начале запроса, поэтому system и неизменяемые секции идут первыми.
+pub fn usage_line(usage: Option<&Usage>) -> String {
+    match usage {
+        Some(u) => format!("— usage: prompt={}, cached={}, completion={}, total={}",
+            u.input_tokens, u.input_tokens_details.cached_tokens.unwrap_or(0), u.output_tokens, u.total_tokens),
+        None => "— usage: prompt=0, cached=0, completion=0, total=0".to_string(),
+    }
+}
+
+/// Как выполнять вызов LLM (explain, summarize --llm, ask): кэш ответов и --dry-run
+#[derive(Args, Debug, Clone, Default)]
+pub struct CallOpts {
+    /// Собрать запрос, записать его в лог и напечатать с оценкой токенов — без вызова API
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Не читать и не писать кэш ответов
+    #[arg(long)]
+    pub no_cache: bool,
+    /// Не брать ответ из кэша, а запросить заново и перезаписать
+    #[arg(long, conflicts_with = "no_cache")]
+    pub refresh: bool,
+    /// Вызвать модель, даже если дневной бюджет токенов (GPTCLI_DAILY_TOKENS) исчерпан
+    #[arg(long)]
+    pub over_budget: bool,
+    /// --dry-run без печати сообщений запроса, только оценка в stderr (bench)
+    #[arg(skip)]
+    pub quiet: bool,
+}
+
+/// Дневной бюджет токенов: GPTCLI_DAILY_TOKENS="200000" — сумма total_tokens из usage_log за сутки (UTC)
+fn daily_budget() -> Option<i64> {
+    static BUDGET: OnceLock<Option<i64>> = OnceLock::new();
+    *BUDGET.get_or_init(|| {
+        let spec = std::env::var("GPTCLI_DAILY_TOKENS").ok()?;
+        let parsed = spec.trim().parse::<i64>().ok().filter(|n| *n > 0);
+        if parsed.is_none() { eprintln!("⚠ GPTCLI_DAILY_TOKENS: ignoring '{spec}' (expected a positive token count)"); }
+        parsed
+    })
+}
+
+/// Потрачено сегодня (UTC, как группирует budget --local)
+fn tokens_today(root: &Path) -> Result<i64> {
+    let conn = crate::db::open_db(root)?;
+    Ok(conn.query_row(
+        "SELECT COALESCE(SUM(total_tokens),0) FROM usage_log WHERE date(ts,'unixepoch')=date('now')",
+        [], |r| r.get(0))?)
+}
+
+/// Перед вызовом: бюджет исчерпан — ошибка, с --over-budget — предупреждение. Ответ из кэша
+/// токенов не стоит и проходит всегда
+pub fn check_budget(root: &Path, args: &CreateResponse, call: &CallOpts) -> Result<()> {
+    let Some(budget) = daily_budget() else { return Ok(()) };
+    if !call.no_cache && !call.refresh && cache_key(args).is_some_and(|k| cache_get(root, &k).is_some()) {
+        return Ok(());
+    }
+    let spent = tokens_today(root)?;
+    if spent < budget { return Ok(()); }
+    if call.over_budget {
+        eprintln!("⚠ daily token budget spent ({spent} of {budget}); calling anyway (--over-budget)");
+        return Ok(());
+    }
+    bail!("daily token budget spent: {spent} of {budget} tokens today (GPTCLI_DAILY_TOKENS); --over-budget to call anyway");
+}
+
+fn cache_key(args: &CreateResponse) -> Option<String> {
+    serde_json::to_vec(args).map(|b| format!("{:x}", Sha256::digest(b))).ok()
+}
+
+/// Запрос к Responses API через кэш. Ключ — sha256 запроса целиком: модель, system, факты
+/// и параметры генерации, так что другой --max-output или язык ответа — другой ключ.
+/// Второе значение — ответ из кэша (токены не потрачены). Кэшируем только завершённые ответы
+/// с текстом и без отказа; сбой самого кэша — предупреждение, не ошибка команды
+pub async fn create_response(root: &Path, args: CreateResponse, call: &CallOpts)
+    -> std::result::Result<(Response, bool), OpenAIError> {
+    let key = cache_key(&args);
+    let use_cache = !call.no_cache && key.is_some();
+    if use_cache && !call.refresh && let Some(resp) = cache_get(root, key.as_deref().unwrap_or_default()) {
+        return Ok((resp, true));
+    }
+    let model = args.model.clone();
+    let resp = Client::new().responses().create(args).await?;
+    let text = crate::commands::output_text(&resp);
+    // отказ не кэшируем: тот же запрос при повторе может пройти
+    if use_cache && resp.status == Status::Completed && refusal(&resp).is_none() && let Some(text) = text {
+        let res = (|| -> Result<()> {
+            let conn = crate::db::open_db(root)?;
+            conn.execute(
+                "INSERT OR REPLACE INTO response_cache(key,model,created_at,text,usage_json,response_json)
+                 VALUES(?1,?2,?3,?4,?5,?6)",
+                rusqlite::params![key, model, OffsetDateTime::now_utc().unix_timestamp(), text,
+                    resp.usage.as_ref().map(serde_json::to_string).transpose()?, serde_json::to_string(&resp)?],
+            )?;
+            Ok(())
+        })();
+        if let Err(e) = res {
+            eprintln!("⚠ response not cached: {e:#}");
+        }
+    }
+    Ok((resp, false))
+}
+
+/// --dry-run: сообщения запроса как есть (stdout — удобно сравнивать diff'ом в CI) и оценка
+/// размера по chunk::estimate_tokens; верхняя граница стоимости — при полном max_output
+pub fn print_dry_run(args: &CreateResponse, req_path: &Path, call: &CallOpts) {
+    let est = crate::commands::chunk::estimate_tokens;
+    let mut input_tokens = 0;
+    match &args.input {
+        Input::Text(text) => {
+            input_tokens += est(text);
+            if !call.quiet { println!("[User]\n{text}\n"); }
+        }
+        Input::Items(items) => for item in items {
+            let InputItem::Message(msg) = item else { continue };
+            let InputContent::TextInput(text) = &msg.content else { continue };
+            input_tokens += est(text);
+            if !call.quiet { println!("[{:?}]\n{text}\n", msg.role); }
+        },
+    }
+    let max_output = args.max_output_tokens.unwrap_or(0);
+    let cost = crate::pricing::cost_for(&args.model, input_tokens, max_output as i64);
+    eprintln!("— dry run: ~{input_tokens} input tokens, max output {max_output}, cost up to {}; API not called",
+        crate::pricing::fmt_cost(cost));
+    eprintln!("— raw request: {}", req_path.display());
+}
+
+fn cache_get(root: &Path, key: &str) -> Option<Response> {
+    let conn = crate::db::open_db(root).ok()?;
+    let json: String = conn.query_row("SELECT response_json FROM response_cache WHERE key=?1",
+        rusqlite::params![key], |r| r.get(0)).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// После вызова: usage, стоимость и запись в usage_log; ответ из кэша ничего не стоил
+pub fn report_usage(root: &Path, command: &str, model: &str, resp: &Response, cached: bool) {
+    if cached {
+        eprintln!("— (cached): same request answered before, no tokens spent; --refresh to ask again");
+        return;
+    }
+    // usage может отсутствовать — учитываем это
+    eprintln!("{}", usage_line(resp.usage.as_ref()));
+    eprintln!("{}", crate::pricing::cost_line(model, resp.usage.as_ref()));
+    record_usage(root, command, model, resp.usage.as_ref());
+    if let Some(budget) = daily_budget() && let Ok(spent) = tokens_today(root) {
+        match budget - spent {
+            left if left > 0 => eprintln!("— daily budget: {left} of {budget} tokens left today"),
+            left => eprintln!("— daily budget: spent, {} tokens over {budget}", -left),
+        }
+    }
+}
+
+/// Записать расход вызова в usage_log (для `budget --local`). Ошибка записи не роняет команду.
+pub fn record_usage(root: &Path, command: &str, model: &str, usage: Option<&Usage>) {
+    let Some(u) = usage else { return };
+    record_tokens(root, command, model, u.input_tokens, u.output_tokens, u.total_tokens);
+}
+
+/// Строка usage_log из готовых чисел — для вызовов вне Responses API (эмбеддинги: output = 0)
+pub fn record_tokens(root: &Path, command: &str, model: &str, input: u32, output: u32, total: u32) {
+    let res = (|| -> Result<()> {
+        let ns = crate::state::ProjectState::load(root)?.active_namespace();
+        let conn = crate::db::open_db(root)?;
+        conn.execute(
+            "INSERT INTO usage_log(ts,command,model,input_tokens,output_tokens,total_tokens,namespace)
+             VALUES(?1,?2,?3,?4,?5,?6,?7)",
+            rusqlite::params![OffsetDateTime::now_utc().unix_timestamp(), command, model, input, output, total, ns],
+        )?;
+        Ok(())
+    })();
+    if let Err(e) = res {
+        eprintln!("⚠ usage not recorded: {e:#}");
+    }
+}
+
+/// Вызов упал (400 на слишком большой вход и т.п.): куда записан запрос и из чего он состоит.
+/// Секции — строки вида `[NAME]` в facts; размеры — оценка chunk::estimate_tokens.
+pub fn report_failed_request(req_path: &Path, system: &str, facts: &str) {
+    let est = crate::commands::chunk::estimate_tokens;
+    let mut sections: Vec<(&str, String)> = Vec::new();
+    let mut cur = ("(preamble)", String::new());
+    for line in facts.lines() {
+        let t = line.trim();
+        if t.len() > 2 && t.starts_with('[') && t.ends_with(']') && !t[1..t.len()-1].contains(['[', ']']) {
+            sections.push(std::mem::replace(&mut cur, (t, String::new())));
+        } else {
+            cur.1.push_str(line);
+            cur.1.push('\n');
+        }
+    }
+    sections.push(cur);
+    let sizes = sections.iter()
+        .filter(|(_, body)| !body.trim().is_empty())
+        .map(|(name, body)| format!("{name} ~{}", est(body)))
+        .collect::<Vec<_>>().join(", ");
+    eprintln!("— request failed; sent request: {}", req_path.display());
+    eprintln!("— ~{} tokens total (system ~{}, facts ~{}): {sizes}", est(system) + est(facts), est(system), est(facts));
+}
+
+/// Пути для сырого запроса/ответа: каталог GPTCLI_LOG_DIR или `<root>/.gptcli/logs`
+/// (не общий /tmp — там промпты видны другим пользователям, а на Windows его нет)
+pub fn request_log_paths(root: &Path, tag: &str, model: &str) -> Result<(PathBuf, PathBuf)> {
+    let dir = std::env::var_os("GPTCLI_LOG_DIR").map(PathBuf::from).unwrap_or_else(|| root.join(".gptcli/logs"));
+    std::fs::create_dir_all(&dir).with_context(|| format!("create log dir {}", dir.display()))?;
+    let ts = OffsetDateTime::now_utc().unix_timestamp();
+    // имя модели может содержать разделители пути (org/model у совместимых серверов)
+    let model = model.replace(['/', '\\', ':'], "_");
+    Ok((dir.join(format!("gptcli-{tag}-req-{model}-{ts}.json")), dir.join(format!("gptcli-{tag}-resp-{model}-{ts}.json"))))
+}
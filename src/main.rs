@@ -6,12 +6,39 @@ mod state;
 mod fs;
 mod commands;
 mod db;
+mod llm;
+mod plan;
+mod pricing;
+mod embeddings;
+mod hyperlink;
 
-use commands::{init, scan, chunk, index, reindex_changed, stats, summarize, budget};
+use commands::{init, scan, chunk, index, reindex_changed, stats, summarize, budget, explain, ask, prompt, thread, sql, export, import, symbols, search, bench};
 
 #[derive(Parser)]
 #[command(name="gptcli", version, about="Project-aware CLI for RAG + code edits")]
 struct Cli {
+    /// Кодировка исходников (напр. windows-1251); по умолчанию UTF-8, а явно не-UTF-8 файлы —
+    /// cp1251 или cp1252 по эвристике. Также GPTCLI_ENCODING
+    #[arg(long, global = true)]
+    encoding: Option<String>,
+
+    /// Корень проекта вместо `git rev-parse --show-toplevel`; также GPTCLI_ROOT
+    #[arg(long, global = true)]
+    root: Option<String>,
+
+    /// Работать с подпроектом монорепы (из state.json: subprojects)
+    #[arg(long, global = true)]
+    subproject: Option<String>,
+
+    /// Читать другой namespace той же БД (напр. proj@release) — для команд чтения;
+    /// у init — namespace создаваемого индекса, у import — namespace, куда загрузить выгрузку
+    #[arg(long, global = true)]
+    namespace: Option<String>,
+
+    /// Не делать копию index.sqlite.bak-v{N} перед миграцией схемы БД
+    #[arg(long, global = true)]
+    no_backup: bool,
+
     #[command(subcommand)]
     cmd: Cmd,
 }
@@ -19,22 +46,35 @@ struct Cli {
 #[derive(Subcommand)]
 enum Cmd {
     /// Создать .gptcli и базу состояния
-    Init { #[arg(long)] namespace: Option<String> },
+    /// (namespace нового индекса задаёт общий --namespace)
+    Init {
+        /// Подкаталог-подпроект монорепы (можно несколько раз)
+        #[arg(long = "subproject-dir")] subprojects: Vec<String>,
+        /// Шаблон ссылок на web-UI с {path} и {line}; по умолчанию — из remote GitHub/GitLab
+        #[arg(long)] web_base: Option<String>,
+        /// Перезаписать существующий .gptcli/state.json
+        #[arg(long)] force: bool,
+        /// Показать, что будет создано или перезаписано, ничего не записывая
+        #[arg(long)] dry_run: bool,
+    },
 
     /// Просканировать дерево проекта
-    Scan {},
+    Scan(scan::ScanArgs),
 
-    /// Разрезать файлы на логические чанки (пока заглушка)
-    Chunk {},
+    /// Пересобрать чанки по сохранённым тегам (без ctags) — для подбора стратегии чанкинга
+    Chunk(chunk::ChunkArgs),
 
-    /// Записать чанки в БД / подготовить индекс (заглушка)
-    Index {},
+    /// Записать чанки в БД / подготовить индекс
+    Index(index::IndexArgs),
 
-    /// Переиндексировать только изменённые (заглушка)
-    ReindexChanged {},
+    /// Переиндексировать только изменённые, не трогая неизменившиеся чанки
+    ReindexChanged(index::IndexArgs),
 
     /// Показать статистику индекса/состояния
-    Stats {},
+    Stats {
+        /// Сводная таблица по всем namespace в БД (файлы/теги/чанки) вместо деталей активного
+        #[arg(long)] all_namespaces: bool,
+    },
 
     /// Сгенерировать секционный обзор проекта для LLM
     Summarize {
@@ -43,22 +83,97 @@ enum Cmd {
     #[arg(long, default_value_t=1200)] max_output: usize,
     #[arg(long)] system_file: Option<String>,
     #[arg(long, default_value="summarize.txt")] facts: String,
+    /// С --llm: собрать факты из индекса сейчас, без файла --facts
+    #[arg(long, requires = "llm", conflicts_with = "facts")] facts_from_index: bool,
+    /// Добавить [RECENT ACTIVITY]: последние N коммитов (по умолчанию 20)
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "20")] include_git_log: Option<usize>,
+    /// Обзор подсистемы: scope (net::) или префикс пути (src/net) для STRUCTURE/ENTRYPOINTS/TODOs
+    #[arg(long)] focus: Option<String>,
+    #[command(flatten)] gen_opts: llm::GenOpts,
+    #[command(flatten)] call: llm::CallOpts,
     },
 
     /// Объяснить назначение и работу функции/класса
-    Explain {
-        #[arg(long)] symbol: Option<String>,    // напр. "net::TlsClient::handshake"
-        #[arg(long)] file: Option<String>,      // относительный путь
-        #[arg(long)] lines: Option<String>,     // "A:B"
-        #[arg(long, default_value="gpt-4.1-mini")] model: String,
-        #[arg(long, default_value_t=900)] max_output: u32,
-        #[arg(long, default_value_t=15)] window: u32,   // контекст ±N строк
-    },
+    Explain(explain::ExplainArgs),
+    /// Ответить на вопрос по коду: поиск по индексу + LLM, со ссылками path:line
+    Ask(ask::AskArgs),
+    /// Библиотека именованных шаблонов для ask --prompt (.gptcli/prompts.json)
+    #[command(subcommand)]
+    Prompt(prompt::PromptCmd),
+    /// Диалоги для ask --thread: new, list, switch
+    #[command(subcommand)]
+    Thread(thread::ThreadCmd),
     /// Показать бюджет
-    Budget {},
+    Budget(budget::BudgetArgs),
+
+    /// Выполнить SELECT/EXPLAIN-запрос к БД индекса (только чтение)
+    Sql {
+        query: String,
+        #[arg(long)] json: bool,
+    },
+
+    /// Выгрузить индекс активного namespace (files/tags/chunks) в JSON/NDJSON
+    Export {
+        #[arg(long)] out: std::path::PathBuf,
+        /// ndjson (объект на строку) или json (массив)
+        #[arg(long, default_value = "ndjson")] format: String,
+        /// Что выгрузить: files, tags, chunks (можно несколько раз или через запятую); по умолчанию всё
+        #[arg(long, value_delimiter = ',')] what: Vec<String>,
+    },
+
+    /// Загрузить NDJSON из export в другой namespace (его задаёт общий --namespace, обязателен)
+    Import {
+        #[arg(long)] file: std::path::PathBuf,
+        /// Показать, сколько строк загрузится, ничего не записывая
+        #[arg(long)] dry_run: bool,
+    },
+
+    /// Поиск чанков: FTS5 (по словам) или --semantic (по смыслу, нужен index --embed)
+    Search(search::SearchArgs),
+
+    /// Список символов из индекса: kind, fqn, path:line, signature
+    Symbols {
+        /// Шаблон имени или scope::name: glob (*, ?), LIKE (%) или подстрока
+        #[arg(long)] pattern: Option<String>,
+        /// Вид тега ctags (function, class, ...)
+        #[arg(long)] kind: Option<String>,
+        /// Только этот файл (путь от корня проекта)
+        #[arg(long)] file: Option<String>,
+        #[arg(long, default_value_t = 50)] limit: usize,
+    },
+
+    /// Замерить scan/index/FTS-запросы/ask (JSON-отчёт) на временной копии БД: индекс не меняется
+    #[command(hide = true)]
+    Bench {
+        /// FTS-запрос (можно несколько раз); по умолчанию — самые частые имена символов
+        #[arg(long = "query")] queries: Vec<String>,
+        /// Сколько раз прогнать набор запросов
+        #[arg(long, default_value_t = 10)] repeat: usize,
+        /// Записать отчёт в файл вместо stdout
+        #[arg(long)] out: Option<String>,
+    },
 
 }
 
+impl Cmd {
+    /// --dry-run любой команды: open_db тогда не создаёт и не мигрирует БД
+    fn dry_run(&self) -> bool {
+        match self {
+            Cmd::Init { dry_run, .. } | Cmd::Import { dry_run, .. } => *dry_run,
+            Cmd::Scan(a) => a.dry_run,
+            Cmd::Chunk(a) => a.dry_run,
+            Cmd::Index(a) | Cmd::ReindexChanged(a) => a.dry_run,
+            Cmd::Summarize { call, .. } => call.dry_run,
+            Cmd::Explain(a) => a.call.dry_run,
+            Cmd::Ask(a) => a.call.dry_run,
+            Cmd::Prompt(c) => c.dry_run(),
+            Cmd::Thread(c) => c.dry_run(),
+            Cmd::Stats { .. } | Cmd::Budget(_) | Cmd::Sql { .. } | Cmd::Export { .. }
+                | Cmd::Search(_) | Cmd::Symbols { .. } | Cmd::Bench { .. } => false,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
 
@@ -72,22 +187,60 @@ async fn main() -> Result<()> {
         .init();
 
     let cli = Cli::parse();
+    if let Some(label) = cli.encoding.clone().or_else(|| std::env::var("GPTCLI_ENCODING").ok()) {
+        fs::set_text_encoding(&label)?;
+    }
+    if let Some(dir) = cli.root.clone().or_else(|| std::env::var("GPTCLI_ROOT").ok()) {
+        fs::set_project_root(&dir)?;
+    }
+    db::set_migration_backup(!cli.no_backup);
+    db::set_dry_run(cli.cmd.dry_run());
+    if let Some(name) = &cli.subproject {
+        state::select_subproject(name);
+    }
+    if let Some(ns) = &cli.namespace && !matches!(cli.cmd, Cmd::Init { .. } | Cmd::Import { .. }) {
+        // индекс пишется из рабочего дерева — под чужим namespace он бы смешал ветки
+        if matches!(cli.cmd, Cmd::Scan(_) | Cmd::Chunk(_) | Cmd::Index(_) | Cmd::ReindexChanged(_) | Cmd::Bench { .. }) {
+            anyhow::bail!("--namespace works only with read commands; check out the branch to index it");
+        }
+        state::select_namespace(ns);
+    }
     match cli.cmd {
-        Cmd::Init { namespace } => init::run(namespace),
-        Cmd::Scan {} => scan::run(),
-        Cmd::Chunk {} => chunk::run(),
-        Cmd::Index {} => index::run(),
-        Cmd::ReindexChanged {} => reindex_changed::run(),
-        Cmd::Stats {} => stats::run(),
-        Cmd::Summarize { llm, model, max_output, system_file, facts } => {
+        Cmd::Init { subprojects, web_base, force, dry_run } => init::run(cli.namespace, subprojects, web_base, force, dry_run),
+        Cmd::Scan(args) => scan::run(args),
+        Cmd::Chunk(args) => chunk::run(args),
+        Cmd::Index(args) => {
+            let embed = args.embed.clone().filter(|_| !args.dry_run);
+            index::run(args)?;
+            match embed { Some(m) => embeddings::run(m).await, None => Ok(()) }
+        }
+        Cmd::ReindexChanged(args) => {
+            let embed = args.embed.clone().filter(|_| !args.dry_run);
+            reindex_changed::run(args)?;
+            match embed { Some(m) => embeddings::run(m).await, None => Ok(()) }
+        }
+        Cmd::Stats { all_namespaces } => stats::run(all_namespaces),
+        Cmd::Summarize { llm, model, max_output, system_file, facts, facts_from_index, include_git_log, focus, gen_opts, call } => {
+            let opts = summarize::FactsOpts { build_limit: max_output, git_log: include_git_log, focus: summarize::Focus(focus) };
             if llm {
-                summarize::run_llm(model, max_output, system_file, facts).await
+                summarize::run_llm(model, max_output, system_file, facts, facts_from_index.then_some(opts), &gen_opts, &call).await
             } else {
-                summarize::run(max_output)
+                summarize::run(opts)
             }
         },
-        Cmd::Explain { symbol, file, lines, model, max_output, window } =>
-            commands::explain::run(symbol, file, lines, model, max_output, window).await,
-        Cmd::Budget {} => budget::run().await,
+        Cmd::Explain(args) => explain::run(args).await,
+        Cmd::Ask(args) => ask::run(args).await,
+        Cmd::Prompt(cmd) => prompt::run(cmd),
+        Cmd::Thread(cmd) => thread::run(cmd),
+        Cmd::Budget(a) => budget::run(a).await,
+        Cmd::Sql { query, json } => sql::run(query, json),
+        Cmd::Export { out, format, what } => export::run(out, format, what),
+        Cmd::Import { file, dry_run } => {
+            let Some(ns) = cli.namespace else { anyhow::bail!("import needs --namespace: the namespace to load into") };
+            import::run(file, ns, dry_run)
+        }
+        Cmd::Search(args) => search::run(args).await,
+        Cmd::Symbols { pattern, kind, file, limit } => symbols::run(pattern, kind, file, limit),
+        Cmd::Bench { queries, repeat, out } => bench::run(queries, repeat, out).await,
     }
 }
@@ -0,0 +1,28 @@
+/// Отчёт `--dry-run`: что команда изменила бы (счётчики и несколько примеров), без записи
+pub struct Plan {
+    cmd: &'static str,
+    items: Vec<(String, usize, Vec<String>)>,
+}
+
+impl Plan {
+    pub fn new(cmd: &'static str) -> Self {
+        Self { cmd, items: Vec::new() }
+    }
+
+    /// Действие с числом затронутых объектов; примеры — первые несколько путей
+    pub fn add(&mut self, action: &str, count: usize, examples: &[String]) {
+        self.items.push((action.to_string(), count, examples.iter().take(5).cloned().collect()));
+    }
+
+    pub fn print(&self) {
+        println!("dry-run: {} — ничего не записано", self.cmd);
+        for (action, count, examples) in &self.items {
+            if examples.is_empty() || *count == 0 {
+                println!("  {action}: {count}");
+            } else {
+                let more = if *count > examples.len() { ", …" } else { "" };
+                println!("  {action}: {count} ({}{more})", examples.join(", "));
+            }
+        }
+    }
+}
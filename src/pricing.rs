@@ -0,0 +1,53 @@
+use async_openai::types::responses::Usage;
+use std::sync::OnceLock;
+
+/// Цены моделей, USD за 1k токенов: GPTCLI_PRICING="gpt-4.1-mini=0.0004/0.0016,gpt-4.1=0.002/0.008"
+/// (вход/выход). Встроенной таблицы нет намеренно — устаревшие цены хуже, чем «unknown».
+struct Price {
+    model: String,
+    input_per_1k: f64,
+    output_per_1k: f64,
+}
+
+fn prices() -> &'static [Price] {
+    static PRICES: OnceLock<Vec<Price>> = OnceLock::new();
+    PRICES.get_or_init(|| {
+        let Ok(spec) = std::env::var("GPTCLI_PRICING") else { return Vec::new() };
+        spec.split(',').map(str::trim).filter(|e| !e.is_empty()).filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(model, rates)| {
+                let (i, o) = rates.split_once('/')?;
+                Some(Price { model: model.trim().to_string(), input_per_1k: i.trim().parse().ok()?, output_per_1k: o.trim().parse().ok()? })
+            });
+            if parsed.is_none() { eprintln!("⚠ GPTCLI_PRICING: ignoring malformed entry '{entry}' (expected model=in/out)"); }
+            parsed
+        }).collect()
+    })
+}
+
+/// Стоимость по суммам токенов; модель ищется по самому длинному префиксу (датированные снапшоты).
+/// None — для модели нет цены.
+pub fn cost_for(model: &str, input_tokens: i64, output_tokens: i64) -> Option<f64> {
+    let p = prices().iter()
+        .filter(|p| model.starts_with(&p.model))
+        .max_by_key(|p| p.model.len())?;
+    Some(input_tokens as f64 / 1000.0 * p.input_per_1k + output_tokens as f64 / 1000.0 * p.output_per_1k)
+}
+
+pub fn estimate_cost(model: &str, usage: &Usage) -> Option<f64> {
+    cost_for(model, usage.input_tokens as i64, usage.output_tokens as i64)
+}
+
+pub fn fmt_cost(cost: Option<f64>) -> String {
+    match cost {
+        Some(c) => format!("${c:.4}"),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Строка для stderr рядом с usage
+pub fn cost_line(model: &str, usage: Option<&Usage>) -> String {
+    match usage.and_then(|u| estimate_cost(model, u)) {
+        Some(c) => format!("— estimated cost: ${c:.4}"),
+        None => "— cost: unknown".to_string(),
+    }
+}
@@ -1,6 +1,22 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf, time::SystemTime};
+use std::{fs, path::{Path, PathBuf}, sync::OnceLock, time::SystemTime};
+
+use crate::fs as ufs;
+
+/// Выбранный через --subproject подпроект монорепы
+static SUBPROJECT: OnceLock<String> = OnceLock::new();
+
+pub fn select_subproject(name: &str) {
+    let _ = SUBPROJECT.set(name.trim_matches('/').to_string());
+}
+
+/// Namespace из --namespace: читать другую ветку той же БД без checkout (state.json не меняется)
+static NAMESPACE: OnceLock<String> = OnceLock::new();
+
+pub fn select_namespace(ns: &str) {
+    let _ = NAMESPACE.set(ns.to_string());
+}
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct ProjectState {
@@ -9,29 +25,63 @@ pub struct ProjectState {
     pub current_thread_id: Option<String>,
     pub last_head: Option<String>,  // короткий SHA, если нужно
     pub created_at: i64,
+    /// Подкаталоги монорепы с отдельными индексами в общей БД
+    #[serde(default)]
+    pub subprojects: Vec<String>,
+    /// git remote (origin) и его ветка по умолчанию — на момент init
+    #[serde(default)]
+    pub remote_url: Option<String>,
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    /// Шаблон web-ссылки на строку: `https://host/o/r/blob/main/{path}#L{line}`
+    #[serde(default)]
+    pub web_base: Option<String>,
+    /// Активный подпроект (не сохраняется)
+    #[serde(skip)]
+    pub subproject: Option<String>,
 }
 
 impl ProjectState {
-    pub fn path(root: &PathBuf) -> PathBuf {
+    pub fn path(root: &Path) -> PathBuf {
         root.join(".gptcli/state.json")
     }
 
-    pub fn load(root: &PathBuf) -> Result<Self> {
+    pub fn load(root: &Path) -> Result<Self> {
         let p = Self::path(root);
         let s = fs::read_to_string(&p)
             .with_context(|| format!("state file not found: {}", p.display()))?;
-        Ok(serde_json::from_str(&s)?)
+        let mut st: Self = serde_json::from_str(&s)?;
+        if let Some(name) = SUBPROJECT.get() {
+            // по полному пути или по имени последнего каталога
+            let Some(dir) = st.subprojects.iter()
+                .find(|d| d.as_str() == name || d.rsplit('/').next() == Some(name.as_str()))
+                .cloned()
+            else {
+                bail!("unknown subproject '{name}', known: [{}]", st.subprojects.join(", "));
+            };
+            st.subproject = Some(dir);
+        }
+        Ok(st)
+    }
+
+    /// Namespace для команд: repo@branch (или --namespace), либо repo@branch:<subdir> при --subproject
+    pub fn active_namespace(&self) -> String {
+        let ns = NAMESPACE.get().unwrap_or(&self.namespace);
+        match &self.subproject {
+            Some(dir) => format!("{ns}:{dir}"),
+            None => ns.clone(),
+        }
     }
 
     pub fn save(&self) -> Result<()> {
         let p = Self::path(&self.project_root);
         if let Some(parent) = p.parent() { fs::create_dir_all(parent)?; }
         let data = serde_json::to_string_pretty(self)?;
-        fs::write(&p, data)?;
+        ufs::write_atomic(&p, data.as_bytes())?;
         Ok(())
     }
 
-    pub fn new(root: PathBuf, namespace: String) -> Result<Self> {
+    pub fn new(root: PathBuf, namespace: String, subprojects: Vec<String>) -> Result<Self> {
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
@@ -42,6 +92,34 @@ impl ProjectState {
             current_thread_id: None,
             last_head: None,
             created_at: now,
+            subprojects,
+            subproject: None,
+            remote_url: None,
+            default_branch: None,
+            web_base: None,
         })
     }
+
+    /// Шаблон ссылок на web-UI: явный --web-base, иначе выводим из remote GitHub/GitLab
+    pub fn web_template(&self) -> Option<String> {
+        if let Some(t) = &self.web_base { return Some(t.clone()); }
+        let url = self.remote_url.as_deref()?;
+        // git@host:owner/repo.git → https://host/owner/repo
+        let https = if let Some(rest) = url.strip_prefix("git@") {
+            format!("https://{}", rest.replacen(':', "/", 1))
+        } else if url.starts_with("https://") || url.starts_with("http://") {
+            url.to_string()
+        } else {
+            return None;
+        };
+        let base = https.trim_end_matches('/').trim_end_matches(".git");
+        let branch = self.default_branch.as_deref().unwrap_or("main");
+        if base.contains("github") {
+            Some(format!("{base}/blob/{branch}/{{path}}#L{{line}}"))
+        } else if base.contains("gitlab") {
+            Some(format!("{base}/-/blob/{branch}/{{path}}#L{{line}}"))
+        } else {
+            None
+        }
+    }
 }